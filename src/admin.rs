@@ -0,0 +1,188 @@
+use crate::{
+    approvals::ApprovalQueue,
+    errors::{into_response, AppError},
+    lockout::Lockout,
+};
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use http_body_util::{BodyExt, Full};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::{UnixListener, UnixStream};
+
+/// Operator-only endpoints, served on a unix socket so they are never reachable
+/// through the public listener or a tunnel.
+#[derive(Clone)]
+pub struct AdminState {
+    pub approvals: Option<Arc<ApprovalQueue>>,
+    pub lockout: Arc<Lockout>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DecisionBody {
+    #[serde(default)]
+    note: Option<String>,
+}
+
+pub fn router(state: AdminState) -> Router {
+    Router::new()
+        .route("/approvals", get(list_approvals))
+        .route("/approvals/:id/approve", post(approve))
+        .route("/approvals/:id/deny", post(deny))
+        .route("/bans", get(list_bans).delete(clear_bans))
+        .route("/bans/:ip", delete(clear_ban))
+        .with_state(state)
+}
+
+fn approvals(state: &AdminState) -> Result<&ApprovalQueue, AppError> {
+    state
+        .approvals
+        .as_deref()
+        .ok_or_else(|| AppError::ToolError("approvals not configured".into()))
+}
+
+async fn list_approvals(State(state): State<AdminState>) -> Response {
+    match approvals(&state) {
+        Ok(q) => Json(json!({"pending": q.list()})).into_response(),
+        Err(e) => into_response(e).into_response(),
+    }
+}
+
+async fn approve(
+    state: State<AdminState>,
+    id: UrlPath<String>,
+    body: Option<Json<DecisionBody>>,
+) -> Response {
+    decide(state, id, body, true)
+}
+
+async fn deny(
+    state: State<AdminState>,
+    id: UrlPath<String>,
+    body: Option<Json<DecisionBody>>,
+) -> Response {
+    decide(state, id, body, false)
+}
+
+fn decide(
+    State(state): State<AdminState>,
+    UrlPath(id): UrlPath<String>,
+    body: Option<Json<DecisionBody>>,
+    approve: bool,
+) -> Response {
+    let note = body.and_then(|Json(b)| b.note);
+    match approvals(&state) {
+        Ok(q) if q.decide(&id, approve, note) => {
+            (StatusCode::OK, Json(json!({"id": id, "approved": approve}))).into_response()
+        }
+        Ok(_) => into_response(AppError::NotFound).into_response(),
+        Err(e) => into_response(e).into_response(),
+    }
+}
+
+async fn list_bans(State(state): State<AdminState>) -> Response {
+    let bans = state.lockout.bans(std::time::Instant::now());
+    Json(json!({"bans": bans})).into_response()
+}
+
+async fn clear_bans(State(state): State<AdminState>) -> Response {
+    Json(json!({"cleared": state.lockout.clear(None)})).into_response()
+}
+
+async fn clear_ban(State(state): State<AdminState>, UrlPath(ip): UrlPath<String>) -> Response {
+    match ip.parse::<std::net::IpAddr>() {
+        Ok(ip) => Json(json!({"cleared": state.lockout.clear(Some(ip))})).into_response(),
+        Err(_) => into_response(AppError::ToolError("invalid ip".into())).into_response(),
+    }
+}
+
+pub async fn serve(socket: &Path, app: Router) -> anyhow::Result<()> {
+    let listener = bind(socket)?;
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!(error = %e, "admin accept failed");
+                continue;
+            }
+        };
+        let svc = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), svc)
+                .await
+            {
+                tracing::debug!(error = %e, "admin connection error");
+            }
+        });
+    }
+}
+
+/// Binds the admin socket with mode 0600. The socket is created inside a
+/// fresh 0700 directory and renamed into place, so it is never reachable
+/// with looser permissions, and only a socket is ever replaced at `socket`.
+pub(crate) fn bind(socket: &Path) -> anyhow::Result<UnixListener> {
+    match std::fs::symlink_metadata(socket) {
+        // left behind by a previous run; it would make bind fail
+        Ok(m) if m.file_type().is_socket() => std::fs::remove_file(socket)?,
+        Ok(_) => anyhow::bail!("{} exists and is not a socket", socket.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let parent = socket.parent().unwrap_or(Path::new("."));
+    let private = parent.join(format!(".valet-admin.{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = (|| {
+        let tmp = private.join("admin.sock");
+        let listener = UnixListener::bind(&tmp)?;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&tmp, socket)?;
+        Ok(listener)
+    })();
+    let _ = std::fs::remove_dir_all(&private);
+    bound
+}
+
+/// Sends one request to a running server's admin socket.
+pub async fn request(
+    socket: &Path,
+    method: http::Method,
+    path: &str,
+    body: Option<Value>,
+) -> anyhow::Result<Value> {
+    let stream = UnixStream::connect(socket).await?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+    let payload = body.map(|b| b.to_string()).unwrap_or_default();
+    let req = http::Request::builder()
+        .method(method)
+        .uri(path)
+        .header("host", "valet-admin")
+        .header("content-type", "application/json")
+        .body(Full::new(bytes::Bytes::from(payload)))?;
+    let resp = sender.send_request(req).await?;
+    let status = resp.status();
+    let body = resp.into_body().collect().await?.to_bytes();
+    let value: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    if !status.is_success() {
+        let msg = value
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("request failed");
+        anyhow::bail!("{status}: {msg}");
+    }
+    Ok(value)
+}
@@ -0,0 +1,195 @@
+use crate::{
+    config::{ApprovalRule, Approvals},
+    errors::AppError,
+    mcp::registry::runs_commands,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+/// A parked `tools/call` as shown to the operator.
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingCall {
+    pub id: String,
+    pub principal: String,
+    pub tool: String,
+    pub arguments: Value,
+    pub requested_at: u64,
+}
+
+#[derive(Debug)]
+enum Verdict {
+    Approve,
+    Deny(Option<String>),
+}
+
+/// Calls matching an approval rule wait here until an operator approves or
+/// denies them over the admin socket, or the timeout passes.
+#[derive(Debug)]
+pub struct ApprovalQueue {
+    root: PathBuf,
+    rules: Vec<ApprovalRule>,
+    timeout: Duration,
+    pending: Mutex<HashMap<String, (PendingCall, oneshot::Sender<Verdict>)>>,
+}
+
+impl ApprovalQueue {
+    pub fn new(cfg: &Approvals, root: &Path) -> Self {
+        Self {
+            root: dunce::canonicalize(root).unwrap_or_else(|_| root.to_path_buf()),
+            rules: cfg.rules.clone(),
+            timeout: Duration::from_secs(cfg.timeout_s),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn requires_approval(&self, tool: &str, args: &Value) -> bool {
+        self.rules.iter().any(|r| self.rule_matches(r, tool, args))
+    }
+
+    fn rule_matches(&self, rule: &ApprovalRule, tool: &str, args: &Value) -> bool {
+        // an `exec` rule also covers the other ways to start a command
+        let exec = rule.tool == "exec" && runs_commands(tool);
+        if rule.tool != "*" && rule.tool != tool && !exec {
+            return false;
+        }
+        if let Some(cmd) = &rule.cmd {
+            let given = args.get("cmd").and_then(|v| v.as_str()).unwrap_or("");
+            if Path::new(given).file_name() != Path::new(cmd).file_name() {
+                return false;
+            }
+        }
+        if let Some(prefix) = &rule.path {
+            let given = args.get("path").and_then(|v| v.as_str()).unwrap_or("");
+            // a path we can't place under root is treated as matching
+            if let Some(rel) = self.relative(Path::new(given)) {
+                if !rel.starts_with(prefix) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Lexical path relative to root, or None if it uses `..` or lies elsewhere.
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let path = if path.is_absolute() {
+            path.strip_prefix(&self.root).ok()?
+        } else {
+            path
+        };
+        let mut out = PathBuf::new();
+        for c in path.components() {
+            match c {
+                Component::Normal(p) => out.push(p),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        Some(out)
+    }
+
+    /// Parks a call. Dropping the returned handle withdraws it.
+    pub fn submit(self: &Arc<Self>, principal: &str, tool: &str, args: &Value) -> PendingApproval {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        let call = PendingCall {
+            id: id.clone(),
+            principal: principal.to_string(),
+            tool: tool.to_string(),
+            arguments: summarize(args),
+            requested_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        tracing::info!(approval_id = %id, principal, tool, "call awaiting approval");
+        self.pending.lock().unwrap().insert(id.clone(), (call, tx));
+        PendingApproval {
+            queue: self.clone(),
+            id,
+            rx,
+        }
+    }
+
+    pub fn list(&self) -> Vec<PendingCall> {
+        let mut calls: Vec<_> = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(c, _)| c.clone())
+            .collect();
+        calls.sort_by_key(|c| c.requested_at);
+        calls
+    }
+
+    /// Resolves a pending call. Returns false if no such call is waiting.
+    pub fn decide(&self, id: &str, approve: bool, note: Option<String>) -> bool {
+        let Some((call, tx)) = self.pending.lock().unwrap().remove(id) else {
+            return false;
+        };
+        tracing::info!(
+            approval_id = %id,
+            principal = %call.principal,
+            tool = %call.tool,
+            approved = approve,
+            note = note.as_deref(),
+            "approval decided"
+        );
+        let verdict = if approve {
+            Verdict::Approve
+        } else {
+            Verdict::Deny(note)
+        };
+        tx.send(verdict).is_ok()
+    }
+}
+
+/// Arguments with long strings (file contents, stdin) cut down for display.
+fn summarize(args: &Value) -> Value {
+    match args {
+        Value::String(s) if s.len() > 256 => {
+            let cut = (0..=256)
+                .rev()
+                .find(|i| s.is_char_boundary(*i))
+                .unwrap_or(0);
+            Value::String(format!("{}... ({} bytes)", &s[..cut], s.len()))
+        }
+        Value::Array(a) => Value::Array(a.iter().map(summarize).collect()),
+        Value::Object(o) => {
+            Value::Object(o.iter().map(|(k, v)| (k.clone(), summarize(v))).collect())
+        }
+        v => v.clone(),
+    }
+}
+
+pub struct PendingApproval {
+    queue: Arc<ApprovalQueue>,
+    id: String,
+    rx: oneshot::Receiver<Verdict>,
+}
+
+impl PendingApproval {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn wait(mut self) -> Result<(), AppError> {
+        match tokio::time::timeout(self.queue.timeout, &mut self.rx).await {
+            Ok(Ok(Verdict::Approve)) => Ok(()),
+            Ok(Ok(Verdict::Deny(note))) => Err(AppError::ApprovalDenied(note.unwrap_or_default())),
+            _ => Err(AppError::ApprovalTimeout),
+        }
+    }
+}
+
+impl Drop for PendingApproval {
+    fn drop(&mut self) {
+        self.queue.pending.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    config::{Budgets, Quantities},
+    errors::AppError,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Resources consumed by calls. CPU and wall time are kept in milliseconds so
+/// short commands still add up; limits are configured in seconds.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Usage {
+    pub calls: u64,
+    pub exec_cpu_ms: u64,
+    pub exec_wall_ms: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.calls += other.calls;
+        self.exec_cpu_ms += other.exec_cpu_ms;
+        self.exec_wall_ms += other.exec_wall_ms;
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+    }
+
+    /// Pairs of (name, used, limit) in the units limits are configured in.
+    fn dimensions(&self, limits: &Quantities) -> [(&'static str, f64, Option<u64>); 5] {
+        [
+            ("calls", self.calls as f64, limits.calls),
+            (
+                "exec_cpu_s",
+                self.exec_cpu_ms as f64 / 1000.0,
+                limits.exec_cpu_s,
+            ),
+            (
+                "exec_wall_s",
+                self.exec_wall_ms as f64 / 1000.0,
+                limits.exec_wall_s,
+            ),
+            ("read_bytes", self.read_bytes as f64, limits.read_bytes),
+            ("write_bytes", self.write_bytes as f64, limits.write_bytes),
+        ]
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Period {
+    key: String,
+    used: Usage,
+}
+
+impl Period {
+    fn roll(&mut self, key: &str) {
+        if self.key != key {
+            self.key = key.to_string();
+            self.used = Usage::default();
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PrincipalUsage {
+    daily: Period,
+    monthly: Period,
+}
+
+/// Daily and monthly usage per principal, persisted to `state_file` after every
+/// change so budgets survive restarts. Periods are UTC calendar days and months.
+#[derive(Debug)]
+pub struct BudgetTracker {
+    cfg: Budgets,
+    state: Mutex<State>,
+    /// Generation of the last snapshot written, so a slow older write never
+    /// overwrites a newer one.
+    written: Arc<Mutex<u64>>,
+}
+
+#[derive(Debug)]
+struct State {
+    usage: HashMap<String, PrincipalUsage>,
+    generation: u64,
+}
+
+fn period_keys() -> (String, String) {
+    let today = time::OffsetDateTime::now_utc().date();
+    let month = format!("{:04}-{:02}", today.year(), u8::from(today.month()));
+    (format!("{month}-{:02}", today.day()), month)
+}
+
+impl BudgetTracker {
+    pub fn load(cfg: &Budgets) -> anyhow::Result<Self> {
+        let state = match std::fs::read(&cfg.state_file) {
+            Ok(raw) => serde_json::from_slice(&raw)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            cfg: cfg.clone(),
+            state: Mutex::new(State {
+                usage: state,
+                generation: 0,
+            }),
+            written: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    /// Rejects the call if any daily or monthly budget is used up; otherwise counts it.
+    pub fn check_and_count(&self, principal: &str) -> Result<(), AppError> {
+        let limits = self.cfg.limits_for(principal);
+        let (day, month) = period_keys();
+        let mut state = self.state.lock().unwrap();
+        let entry = state.usage.entry(principal.to_string()).or_default();
+        entry.daily.roll(&day);
+        entry.monthly.roll(&month);
+        for (period, used, limits) in [
+            ("daily", &entry.daily.used, &limits.daily),
+            ("monthly", &entry.monthly.used, &limits.monthly),
+        ] {
+            for (name, used, limit) in used.dimensions(limits) {
+                if limit.is_some_and(|l| used >= l as f64) {
+                    return Err(AppError::BudgetExceeded(format!("{period} {name}")));
+                }
+            }
+        }
+        let call = Usage {
+            calls: 1,
+            ..Usage::default()
+        };
+        entry.daily.used.add(&call);
+        entry.monthly.used.add(&call);
+        self.persist(&mut state);
+        Ok(())
+    }
+
+    pub fn record(&self, principal: &str, usage: Usage) {
+        let (day, month) = period_keys();
+        let mut state = self.state.lock().unwrap();
+        let entry = state.usage.entry(principal.to_string()).or_default();
+        entry.daily.roll(&day);
+        entry.monthly.roll(&month);
+        entry.daily.used.add(&usage);
+        entry.monthly.used.add(&usage);
+        self.persist(&mut state);
+    }
+
+    pub fn status(&self, principal: &str) -> serde_json::Value {
+        let limits = self.cfg.limits_for(principal);
+        let (day, month) = period_keys();
+        let mut state = self.state.lock().unwrap();
+        let entry = state.usage.entry(principal.to_string()).or_default();
+        entry.daily.roll(&day);
+        entry.monthly.roll(&month);
+        let describe = |p: &Period, limits: &Quantities| {
+            let dims = p.used.dimensions(limits);
+            let used: serde_json::Map<_, _> = dims
+                .iter()
+                .map(|(n, u, _)| (n.to_string(), json!(u)))
+                .collect();
+            let remaining: serde_json::Map<_, _> = dims
+                .iter()
+                .map(|(n, u, l)| (n.to_string(), json!(l.map(|l| (l as f64 - u).max(0.0)))))
+                .collect();
+            json!({"period": p.key, "used": used, "limits": limits, "remaining": remaining})
+        };
+        json!({
+            "principal": principal,
+            "daily": describe(&entry.daily, &limits.daily),
+            "monthly": describe(&entry.monthly, &limits.monthly),
+        })
+    }
+
+    /// Snapshots the state under the lock and writes it off the async runtime,
+    /// so tools/call never waits on the disk.
+    fn persist(&self, state: &mut State) {
+        let raw = match serde_json::to_vec(&state.usage) {
+            Ok(raw) => raw,
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to serialize usage");
+                return;
+            }
+        };
+        state.generation += 1;
+        let (generation, path, written) = (
+            state.generation,
+            self.cfg.state_file.clone(),
+            self.written.clone(),
+        );
+        let write = move || write_snapshot(&path, &raw, generation, &written);
+        match tokio::runtime::Handle::try_current() {
+            Ok(rt) => drop(rt.spawn_blocking(write)),
+            Err(_) => write(),
+        }
+    }
+}
+
+fn write_snapshot(path: &Path, raw: &[u8], generation: u64, written: &Mutex<u64>) {
+    let mut written = written.lock().unwrap();
+    if *written >= generation {
+        return;
+    }
+    let tmp = path.with_extension("tmp");
+    let res = std::fs::write(&tmp, raw).and_then(|_| std::fs::rename(&tmp, path));
+    match res {
+        Ok(()) => *written = generation,
+        Err(e) => tracing::warn!(error = %e, path = %path.display(), "failed to persist usage"),
+    }
+}
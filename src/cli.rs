@@ -0,0 +1,95 @@
+use crate::{admin, config::Config};
+use serde_json::{json, Value};
+
+const USAGE: &str = "usage:
+  valet approvals [list | approve <id> [--note <text>] | deny <id> [--note <text>]]
+  valet bans [list | clear [<ip>]]";
+
+/// Operator subcommands; they talk to a running server over the admin socket.
+pub async fn run(cfg: &Config, args: &[String]) -> anyhow::Result<()> {
+    let Some(admin_cfg) = &cfg.admin else {
+        anyhow::bail!("no [admin] socket configured");
+    };
+    let socket = &admin_cfg.socket;
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["approvals"] | ["approvals", "list"] => {
+            let resp = admin::request(socket, http::Method::GET, "/approvals", None).await?;
+            print_pending(&resp);
+        }
+        ["approvals", verb @ ("approve" | "deny"), id, rest @ ..] => {
+            let note = match rest {
+                [] => None,
+                ["--note", note] => Some(note.to_string()),
+                _ => anyhow::bail!(USAGE),
+            };
+            let path = format!("/approvals/{id}/{verb}");
+            admin::request(
+                socket,
+                http::Method::POST,
+                &path,
+                Some(json!({"note": note})),
+            )
+            .await?;
+            println!(
+                "{id}: {}",
+                if *verb == "approve" {
+                    "approved"
+                } else {
+                    "denied"
+                }
+            );
+        }
+        ["bans"] | ["bans", "list"] => {
+            let resp = admin::request(socket, http::Method::GET, "/bans", None).await?;
+            print_bans(&resp);
+        }
+        ["bans", "clear", rest @ ..] => {
+            let path = match rest {
+                [] => "/bans".to_string(),
+                [ip] => format!("/bans/{ip}"),
+                _ => anyhow::bail!(USAGE),
+            };
+            let resp = admin::request(socket, http::Method::DELETE, &path, None).await?;
+            println!("cleared {}", resp["cleared"]);
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+    Ok(())
+}
+
+fn print_pending(resp: &Value) {
+    let pending = resp["pending"].as_array().cloned().unwrap_or_default();
+    if pending.is_empty() {
+        println!("no pending calls");
+    }
+    for p in pending {
+        println!(
+            "{}  {}  {}  {}",
+            p["id"].as_str().unwrap_or(""),
+            p["principal"].as_str().unwrap_or(""),
+            p["tool"].as_str().unwrap_or(""),
+            p["arguments"]
+        );
+    }
+}
+
+fn print_bans(resp: &Value) {
+    let bans = resp["bans"].as_array().cloned().unwrap_or_default();
+    if bans.is_empty() {
+        println!("no active bans");
+    }
+    for b in bans {
+        println!(
+            "{}  {}  strikes={}  remaining={}s",
+            b["ip"].as_str().unwrap_or(""),
+            b["prefix"].as_str().unwrap_or(""),
+            b["strikes"],
+            b["remaining_s"]
+        );
+    }
+}
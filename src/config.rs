@@ -1,6 +1,9 @@
-use serde::Deserialize;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::num::NonZeroU32;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
@@ -9,6 +12,26 @@ pub struct Config {
     pub auth: Auth,
     pub limits: Limits,
     pub exec: Exec,
+    #[serde(default)]
+    pub paths: PathRules,
+    #[serde(default)]
+    pub redaction: Redaction,
+    #[serde(default)]
+    pub budgets: Option<Budgets>,
+    #[serde(default)]
+    pub admin: Option<Admin>,
+    #[serde(default)]
+    pub approvals: Option<Approvals>,
+    #[serde(default)]
+    pub pty: Pty,
+    #[serde(default)]
+    pub jobs: Jobs,
+    /// Named long-running commands for the `service_*` tools.
+    #[serde(default)]
+    pub services: HashMap<String, Service>,
+    /// File the config was loaded from, kept out of reach of exec children.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -22,15 +45,178 @@ pub struct Server {
     pub port: u16,
     #[serde(default = "default_base_path")]
     pub base_path: String,
+    #[serde(default)]
+    pub tls: Option<Tls>,
 }
 fn default_base_path() -> String {
     "/mcp".to_string()
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// PEM bundle of CAs trusted to sign client certificates; enables mutual TLS.
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    #[serde(default)]
+    pub require_client_cert: bool,
+    /// Client certificate common name -> principal name. Unmapped certificates use their CN.
+    #[serde(default)]
+    pub client_principals: HashMap<String, String>,
+    #[serde(default = "default_tls_reload_s")]
+    pub reload_interval_s: u64,
+}
+fn default_tls_reload_s() -> u64 {
+    30
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Auth {
+    #[serde(default)]
+    pub mode: AuthMode,
+    /// Shared secret embedded in the URL path; required in `token` mode.
+    #[serde(default)]
     pub bearer_token: String,
+    /// Exact origins, `null`, or wildcard subdomain patterns like `https://*.ts.net`.
     pub allowed_origins: Vec<String>,
+    /// Accept requests without an Origin header (server-to-server clients).
+    #[serde(default)]
+    pub allow_missing_origin: bool,
+    /// Separate origin policy for `/healthz`; defaults to the MCP policy above.
+    #[serde(default)]
+    pub healthz: Option<OriginPolicy>,
+    /// Client networks allowed to call Valet; empty allows any address.
+    #[serde(default)]
+    pub allowed_cidrs: Vec<IpNet>,
+    /// Peers whose `Forwarded`/`X-Forwarded-For` headers are trusted (e.g. the Funnel hop).
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    #[serde(default)]
+    pub tailscale: Option<Tailscale>,
+    #[serde(default)]
+    pub hmac: Option<Hmac>,
+    #[serde(default)]
+    pub lockout: LockoutPolicy,
+}
+
+/// Temporary bans for clients that keep failing authentication.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LockoutPolicy {
+    pub enabled: bool,
+    /// Failures within `window_s` that trigger a ban.
+    pub max_failures: u32,
+    pub window_s: u64,
+    /// First ban length; doubles with each further ban up to `max_ban_s`.
+    pub ban_s: u64,
+    pub max_ban_s: u64,
+    /// Client networks never banned.
+    pub exempt: Vec<IpNet>,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_failures: 5,
+            window_s: 300,
+            ban_s: 60,
+            max_ban_s: 3600,
+            exempt: vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Shared token in the URL path.
+    #[default]
+    Token,
+    /// Tailnet identity from `tailscale serve` headers or the tailscaled LocalAPI.
+    Tailscale,
+    /// Every request signed with a shared key; see [`Hmac`].
+    Hmac,
+}
+
+/// Request signing: `X-Valet-Signature` is hex HMAC-SHA256 over
+/// `"{timestamp}\n{nonce}\n"` followed by the request body.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Hmac {
+    pub key: String,
+    /// Accepted difference between `X-Valet-Timestamp` and the server clock.
+    #[serde(default = "default_max_skew_s")]
+    pub max_skew_s: u64,
+    /// Nonces remembered within the skew window; further requests are throttled.
+    #[serde(default = "default_nonce_cache")]
+    pub nonce_cache: usize,
+    #[serde(default = "default_hmac_principal")]
+    pub principal: String,
+}
+fn default_max_skew_s() -> u64 {
+    300
+}
+fn default_nonce_cache() -> usize {
+    10_000
+}
+fn default_hmac_principal() -> String {
+    "hmac".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Tailscale {
+    /// tailscaled LocalAPI socket used for whois lookups.
+    #[serde(default = "default_tailscale_socket")]
+    pub socket: PathBuf,
+    /// Resolve callers without `Tailscale-User-*` headers (e.g. tagged nodes) via whois.
+    #[serde(default = "default_true")]
+    pub use_whois: bool,
+    /// First matching rule wins; callers matching no rule are rejected.
+    #[serde(default)]
+    pub principals: Vec<TailnetPrincipal>,
+}
+fn default_tailscale_socket() -> PathBuf {
+    PathBuf::from("/var/run/tailscaled.socket")
+}
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TailnetPrincipal {
+    /// A login name (`alice@example.com`), a node tag (`tag:ci`), or `*`.
+    #[serde(rename = "match")]
+    pub matches: String,
+    /// Principal name; defaults to the tailnet login or tag that matched.
+    #[serde(default)]
+    pub principal: Option<String>,
+    /// Tool names this principal may call; `*` allows all.
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+fn default_scopes() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OriginPolicy {
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub allow_missing_origin: bool,
+}
+
+impl Auth {
+    pub fn mcp_origins(&self) -> OriginPolicy {
+        OriginPolicy {
+            allowed_origins: self.allowed_origins.clone(),
+            allow_missing_origin: self.allow_missing_origin,
+        }
+    }
+
+    pub fn healthz_origins(&self) -> OriginPolicy {
+        self.healthz.clone().unwrap_or_else(|| self.mcp_origins())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -38,6 +224,78 @@ pub struct Limits {
     pub exec_timeout_s: u64,
     pub max_stdout_kb: usize,
     pub max_request_kb: usize,
+    #[serde(default)]
+    pub rate: RateLimits,
+    #[serde(default = "default_max_concurrent_exec")]
+    pub max_concurrent_exec: usize,
+    #[serde(default = "default_max_concurrent_exec_per_principal")]
+    pub max_concurrent_exec_per_principal: usize,
+    /// Exec calls allowed to wait for a slot; further calls fail with QueueFull.
+    #[serde(default = "default_exec_queue_max")]
+    pub exec_queue_max: usize,
+    #[serde(default = "default_exec_queue_timeout_s")]
+    pub exec_queue_timeout_s: u64,
+    /// Time between SIGTERM and SIGKILL when a command's process group is
+    /// stopped for a timeout or output overflow.
+    #[serde(default = "default_exec_kill_grace_ms")]
+    pub exec_kill_grace_ms: u64,
+    /// Cap on one exec call's stdin, however it is supplied.
+    #[serde(default = "default_max_stdin_kb")]
+    pub max_stdin_kb: usize,
+}
+fn default_max_concurrent_exec() -> usize {
+    4
+}
+fn default_max_concurrent_exec_per_principal() -> usize {
+    2
+}
+fn default_exec_queue_max() -> usize {
+    16
+}
+fn default_exec_queue_timeout_s() -> u64 {
+    30
+}
+fn default_exec_kill_grace_ms() -> u64 {
+    2000
+}
+fn default_max_stdin_kb() -> usize {
+    1024
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimits {
+    pub global: Rate,
+    pub per_principal: Rate,
+    pub per_ip: Rate,
+    /// Extra per-principal quotas for individual tools, keyed by tool name.
+    pub per_tool: HashMap<String, Rate>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            global: Rate::new(20, 40),
+            per_principal: Rate::new(10, 20),
+            per_ip: Rate::new(10, 20),
+            per_tool: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct Rate {
+    pub per_sec: NonZeroU32,
+    pub burst: NonZeroU32,
+}
+
+impl Rate {
+    pub fn new(per_sec: u32, burst: u32) -> Self {
+        Self {
+            per_sec: NonZeroU32::new(per_sec).expect("per_sec > 0"),
+            burst: NonZeroU32::new(burst).expect("burst > 0"),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -45,16 +303,455 @@ pub struct Exec {
     pub allowed_cmds: Vec<String>,
     #[serde(default)]
     pub pass_env: Vec<String>,
+    #[serde(default)]
+    pub landlock: Landlock,
+    #[serde(default)]
+    pub isolation: Isolation,
+    /// Seccomp profile for every command: `default`, `none`, or a name from
+    /// `seccomp_profiles`.
+    #[serde(default = "default_seccomp")]
+    pub seccomp: String,
+    #[serde(default)]
+    pub seccomp_profiles: HashMap<String, SeccompProfile>,
+    #[serde(default)]
+    pub rlimits: Rlimits,
+    /// Refuse to run binaries inside root_dir, where tools can write them.
+    #[serde(default = "default_true")]
+    pub deny_root_binaries: bool,
+    /// Per-command settings keyed by an entry of `allowed_cmds`.
+    #[serde(default)]
+    pub commands: HashMap<String, CommandPolicy>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CommandPolicy {
+    /// Replaces `[exec.landlock]` for this command.
+    #[serde(default)]
+    pub landlock: Option<Landlock>,
+    /// Replaces `[exec.isolation]` for this command.
+    #[serde(default)]
+    pub isolation: Option<Isolation>,
+    /// Replaces `exec.seccomp` for this command.
+    #[serde(default)]
+    pub seccomp: Option<String>,
+    /// Replaces `[exec.rlimits]` for this command.
+    #[serde(default)]
+    pub rlimits: Option<Rlimits>,
+    /// Hex sha256 the binary must match on every call.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub args: Option<ArgPolicy>,
+    /// Working directory relative to root_dir when the call doesn't pass one.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+}
+
+/// Constraints on a command's arguments. Arguments starting with `-` are
+/// flags until a `--`; the rest are positional.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ArgPolicy {
+    /// The first positional argument must be one of these, e.g. git subcommands.
+    pub subcommands: Vec<String>,
+    /// Flags refused anywhere, also in `--flag=value` form; single-letter
+    /// flags are also caught inside bundles like `-xf`.
+    pub deny_flags: Vec<String>,
+    /// Regexes every other positional argument must fully match (any one).
+    pub positional: Vec<String>,
+    pub max_args: Option<usize>,
+    /// Path-like arguments (containing `/`, `.` or `..`, or starting with
+    /// `~`) must resolve inside root_dir.
+    pub paths_in_root: bool,
+}
+
+/// Interactive terminal sessions (`pty_*` tools). Commands go through the
+/// same allowlist and sandbox as `exec`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Pty {
+    pub max_sessions: usize,
+    pub max_sessions_per_principal: usize,
+    /// Sessions nobody has touched for this long are closed.
+    pub idle_timeout_s: u64,
+    /// Unread output kept per session; older bytes are dropped.
+    pub scrollback_kb: usize,
+    pub term: String,
+}
+
+impl Default for Pty {
+    fn default() -> Self {
+        Self {
+            max_sessions: 8,
+            max_sessions_per_principal: 2,
+            idle_timeout_s: 600,
+            scrollback_kb: 256,
+            term: "xterm-256color".to_string(),
+        }
+    }
+}
+
+/// Background jobs (`job_*` tools). Commands go through the same allowlist
+/// and sandbox as `exec` but run past the end of the call that started them.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Jobs {
+    pub max_running: usize,
+    pub max_running_per_principal: usize,
+    /// Default and longest run time of a job.
+    pub timeout_s: u64,
+    /// Output kept per stream; a job writing more is stopped.
+    pub max_output_kb: usize,
+    /// How long finished jobs stay around for `job_status` and `job_output`.
+    pub retention_s: u64,
+    /// Finished jobs kept per principal; the oldest go first.
+    pub max_retained: usize,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self {
+            max_running: 4,
+            max_running_per_principal: 2,
+            timeout_s: 3600,
+            max_output_kb: 8192,
+            retention_s: 3600,
+            max_retained: 32,
+        }
+    }
+}
+
+/// A long-running command started with `service_start`, such as a dev
+/// server or a file watcher. It runs under the same policy as `exec` and
+/// is killed when Valet exits.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Service {
+    pub cmd: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Relative to root_dir; defaults to the command's `[exec.commands]` cwd.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// Set on top of `exec.pass_env`.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub restart: Restart,
+    /// Restarts after a `service_start` before the service is left failed.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    #[serde(default = "default_restart_delay_ms")]
+    pub restart_delay_ms: u64,
+    /// Ready once something accepts connections on this localhost port.
+    #[serde(default)]
+    pub ready_port: Option<u16>,
+    /// Ready once a line of output matches this regex.
+    #[serde(default)]
+    pub ready_log: Option<String>,
+    /// How long `service_start` waits for readiness.
+    #[serde(default = "default_ready_timeout_s")]
+    pub ready_timeout_s: u64,
+    /// Output kept, stdout and stderr interleaved; older bytes are dropped.
+    #[serde(default = "default_log_kb")]
+    pub log_kb: usize,
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+fn default_restart_delay_ms() -> u64 {
+    1000
+}
+fn default_ready_timeout_s() -> u64 {
+    60
+}
+fn default_log_kb() -> usize {
+    1024
+}
+
+/// When a service that exited on its own is started again.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restart {
+    Never,
+    /// After a non-zero exit or a signal.
+    #[default]
+    OnFailure,
+    Always,
+}
+
+/// Resource limits set on each command before exec; unset means inherited.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Rlimits {
+    /// CPU seconds; the process gets SIGXCPU, then SIGKILL a second later.
+    pub cpu_s: Option<u64>,
+    /// Address space in MiB.
+    pub as_mb: Option<u64>,
+    /// Largest file the command may write, in MiB.
+    pub fsize_mb: Option<u64>,
+    pub nofile: Option<u64>,
+    /// Processes for the user the command runs as.
+    pub nproc: Option<u64>,
+}
+
+/// Syscalls that kill the command when made (Linux only).
+#[derive(Debug, Deserialize, Clone)]
+pub struct SeccompProfile {
+    /// `default` adds the built-in list, `none` starts empty.
+    #[serde(default = "default_seccomp")]
+    pub base: String,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+fn default_seccomp() -> String {
+    "default".to_string()
+}
+
+/// Linux namespaces for exec children: own user, mount, PID, IPC and (unless
+/// `network`) network namespace; the filesystem is read-only apart from
+/// root_dir and a private tmpfs on /tmp.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Isolation {
+    pub enabled: bool,
+    /// Keep the host network instead of an empty namespace with only loopback.
+    pub network: bool,
+    pub tmp_size_mb: u64,
+}
+
+impl Default for Isolation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            network: false,
+            tmp_size_mb: 64,
+        }
+    }
+}
+
+/// Landlock ruleset for exec children (Linux 5.13+; best effort on older
+/// kernels). root_dir is always readable.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Landlock {
+    pub enabled: bool,
+    /// Read and execute access.
+    pub read: Vec<PathBuf>,
+    /// Read-write access outside root_dir.
+    pub write: Vec<PathBuf>,
+    /// Writable parts of root_dir, relative to it.
+    pub root_write: Vec<PathBuf>,
+}
+
+impl Default for Landlock {
+    fn default() -> Self {
+        let paths = |p: &[&str]| p.iter().map(PathBuf::from).collect();
+        Self {
+            enabled: true,
+            read: paths(&[
+                "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/opt", "/nix",
+                "/proc", "/sys", "/dev",
+            ]),
+            write: paths(&["/dev/null"]),
+            root_write: paths(&["."]),
+        }
+    }
+}
+
+/// Paths hidden from tools, on top of the root containment check.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct PathRules {
+    /// Also apply `.valetignore` (gitignore syntax) from root_dir.
+    pub valetignore: bool,
+    pub deny: Vec<DenyRule>,
+    pub symlinks: SymlinkPolicy,
+    /// Path-scoped permissions. Paths no rule covers keep every permission.
+    pub permissions: Vec<PermissionRule>,
+}
+
+impl Default for PathRules {
+    fn default() -> Self {
+        Self {
+            valetignore: true,
+            deny: vec![],
+            symlinks: SymlinkPolicy::default(),
+            permissions: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DenyRule {
+    /// gitignore-style patterns relative to root_dir, e.g. `**/*.pem`.
+    pub globs: Vec<String>,
+    #[serde(default = "all_access")]
+    pub access: Vec<Access>,
+    /// Tool names, or `*` for all.
+    #[serde(default = "all_tools")]
+    pub tools: Vec<String>,
+}
+fn all_access() -> Vec<Access> {
+    vec![Access::Read, Access::Write, Access::List]
+}
+fn all_tools() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+    List,
+}
+
+/// How fs tools treat symlinks met while resolving a path.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Any symlink in the path is refused.
+    Deny,
+    /// Symlinks are followed as long as every step stays under root_dir.
+    #[default]
+    FollowWithinRoot,
+}
+
+/// Grants `allow` below `path`. The longest matching path wins; rules naming
+/// the caller are consulted before rules for everyone.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PermissionRule {
+    /// Relative to root_dir, compared by component; `.` is the whole root.
+    pub path: PathBuf,
+    pub allow: Vec<Permission>,
+    /// Principal names this rule applies to; empty means everyone.
+    #[serde(default)]
+    pub principals: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    Read,
+    Write,
+    Create,
+    Delete,
+    ExecCwd,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Create => "create",
+            Permission::Delete => "delete",
+            Permission::ExecCwd => "exec-cwd",
+        }
+    }
+}
+
+/// Secret redaction for fs_read content, exec output and log lines.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Redaction {
+    pub enabled: bool,
+    /// AWS keys, GitHub tokens, private key blocks and JWTs.
+    pub builtin: bool,
+    /// Extra regexes; each match is replaced. Streamed output is matched line by line.
+    pub patterns: Vec<String>,
+    pub replacement: String,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            builtin: true,
+            patterns: vec![],
+            replacement: "[REDACTED]".to_string(),
+        }
+    }
+}
+
+/// Daily and monthly usage budgets, tracked per principal.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Budgets {
+    /// Where usage is persisted so budgets survive restarts.
+    pub state_file: PathBuf,
+    #[serde(default)]
+    pub default: BudgetLimits,
+    /// Replaces `default` entirely for the named principals.
+    #[serde(default)]
+    pub principals: HashMap<String, BudgetLimits>,
+}
+
+impl Budgets {
+    pub fn limits_for(&self, principal: &str) -> &BudgetLimits {
+        self.principals.get(principal).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct BudgetLimits {
+    pub daily: Quantities,
+    pub monthly: Quantities,
+}
+
+/// Budget amounts; unset dimensions are unlimited.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Quantities {
+    pub calls: Option<u64>,
+    pub exec_cpu_s: Option<u64>,
+    pub exec_wall_s: Option<u64>,
+    pub read_bytes: Option<u64>,
+    pub write_bytes: Option<u64>,
+}
+
+/// Local operator interface, used by the `valet approvals` CLI.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Admin {
+    /// Unix socket path; created with mode 0600.
+    pub socket: PathBuf,
+}
+
+/// Calls matching any rule wait for an operator decision before running.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Approvals {
+    #[serde(default = "default_approval_timeout_s")]
+    pub timeout_s: u64,
+    #[serde(default)]
+    pub rules: Vec<ApprovalRule>,
+}
+fn default_approval_timeout_s() -> u64 {
+    300
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApprovalRule {
+    /// Tool name, or `*` for any tool.
+    pub tool: String,
+    /// exec only: command name, compared by file name (`git` matches `/usr/bin/git`).
+    #[serde(default)]
+    pub cmd: Option<String>,
+    /// fs tools: path prefix relative to root_dir, compared by component.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
 }
 
 impl Config {
     pub fn load(path: &Path) -> anyhow::Result<Self> {
         let raw = fs::read_to_string(path)?;
-        if path.extension().map(|e| e == "json").unwrap_or(false) {
-            Ok(serde_json::from_str(&raw)?)
+        let mut cfg: Config = if path.extension().map(|e| e == "json").unwrap_or(false) {
+            serde_json::from_str(&raw)?
         } else {
-            Ok(toml::from_str(&raw)?)
-        }
+            toml::from_str(&raw)?
+        };
+        cfg.source = Some(dunce::canonicalize(path)?);
+        Ok(cfg)
     }
 
     pub fn validate(&self) -> anyhow::Result<()> {
@@ -64,12 +761,41 @@ impl Config {
                 self.root.root_dir.display()
             );
         }
-        if self.auth.bearer_token.trim().is_empty() {
-            anyhow::bail!("bearer_token must not be empty");
+        match self.auth.mode {
+            AuthMode::Token => {
+                if self.auth.bearer_token.trim().is_empty() {
+                    anyhow::bail!("bearer_token must not be empty");
+                }
+            }
+            AuthMode::Tailscale => {
+                let Some(ts) = &self.auth.tailscale else {
+                    anyhow::bail!("auth mode tailscale needs an [auth.tailscale] section");
+                };
+                if ts.principals.is_empty() {
+                    anyhow::bail!("[auth.tailscale] principals must not be empty");
+                }
+            }
+            AuthMode::Hmac => {
+                let Some(h) = &self.auth.hmac else {
+                    anyhow::bail!("auth mode hmac needs an [auth.hmac] section");
+                };
+                if h.key.len() < 32 {
+                    anyhow::bail!("[auth.hmac] key must be at least 32 bytes");
+                }
+                if h.max_skew_s == 0 || h.nonce_cache == 0 {
+                    anyhow::bail!("[auth.hmac] max_skew_s and nonce_cache must be > 0");
+                }
+            }
         }
         if self.auth.allowed_origins.is_empty() {
             anyhow::bail!("allowed_origins must not be empty");
         }
+        let healthz = self.auth.healthz.iter().flat_map(|h| &h.allowed_origins);
+        for o in self.auth.allowed_origins.iter().chain(healthz) {
+            if !crate::security::valid_origin_pattern(o) {
+                anyhow::bail!("invalid allowed origin: {o}");
+            }
+        }
         if self.limits.exec_timeout_s == 0 {
             anyhow::bail!("exec_timeout_s must be > 0");
         }
@@ -79,6 +805,213 @@ impl Config {
         if self.limits.max_stdout_kb == 0 {
             anyhow::bail!("max_stdout_kb must be > 0");
         }
+        if self.limits.max_concurrent_exec == 0
+            || self.limits.max_concurrent_exec_per_principal == 0
+        {
+            anyhow::bail!("max_concurrent_exec limits must be > 0");
+        }
+        for p in &self.redaction.patterns {
+            if let Err(e) = regex::bytes::Regex::new(p) {
+                anyhow::bail!("invalid redaction pattern {p:?}: {e}");
+            }
+        }
+        for rule in &self.paths.permissions {
+            if !is_plain_relative(&rule.path) {
+                anyhow::bail!(
+                    "[[paths.permissions]] path must be relative to root_dir without `..`: {}",
+                    rule.path.display()
+                );
+            }
+        }
+        let per_command = self
+            .exec
+            .commands
+            .values()
+            .filter_map(|c| c.landlock.as_ref());
+        for landlock in std::iter::once(&self.exec.landlock).chain(per_command) {
+            self.validate_landlock(landlock)?;
+        }
+        let per_command = self
+            .exec
+            .commands
+            .values()
+            .filter_map(|c| c.seccomp.as_ref());
+        for name in std::iter::once(&self.exec.seccomp).chain(per_command) {
+            let builtin = name == "default" || name == "none";
+            if !builtin && !self.exec.seccomp_profiles.contains_key(name) {
+                anyhow::bail!("unknown seccomp profile: {name}");
+            }
+        }
+        for (name, profile) in &self.exec.seccomp_profiles {
+            if profile.base != "default" && profile.base != "none" {
+                anyhow::bail!("[exec.seccomp_profiles.{name}] base must be `default` or `none`");
+            }
+            if let Some(bad) = profile
+                .deny
+                .iter()
+                .find(|s| !crate::tools::sandbox::known_syscall(s))
+            {
+                anyhow::bail!("[exec.seccomp_profiles.{name}] unknown syscall: {bad}");
+            }
+        }
+        let per_command = self
+            .exec
+            .commands
+            .values()
+            .filter_map(|c| c.isolation.as_ref());
+        for isolation in std::iter::once(&self.exec.isolation).chain(per_command) {
+            if isolation.enabled && !cfg!(target_os = "linux") {
+                anyhow::bail!("exec isolation needs Linux namespaces");
+            }
+            if isolation.enabled && isolation.tmp_size_mb == 0 {
+                anyhow::bail!("exec isolation tmp_size_mb must be > 0");
+            }
+        }
+        let per_command = self
+            .exec
+            .commands
+            .values()
+            .filter_map(|c| c.rlimits.as_ref());
+        for r in std::iter::once(&self.exec.rlimits).chain(per_command) {
+            let values = [r.cpu_s, r.as_mb, r.fsize_mb, r.nofile, r.nproc];
+            if values.contains(&Some(0)) {
+                anyhow::bail!("exec rlimits must be > 0");
+            }
+        }
+        for (name, cmd) in &self.exec.commands {
+            if cmd.cwd.as_deref().is_some_and(|p| !is_plain_relative(p)) {
+                anyhow::bail!(
+                    "[exec.commands.{name}] cwd must be relative to root_dir without `..`"
+                );
+            }
+            for p in cmd.args.iter().flat_map(|a| &a.positional) {
+                if let Err(e) = regex::Regex::new(p) {
+                    anyhow::bail!(
+                        "[exec.commands.{name}.args] invalid positional pattern {p:?}: {e}"
+                    );
+                }
+            }
+            let Some(sha) = &cmd.sha256 else { continue };
+            if sha.len() != 64 || !sha.bytes().all(|b| b.is_ascii_hexdigit()) {
+                anyhow::bail!("[exec.commands.{name}] sha256 must be 64 hex digits");
+            }
+        }
+        if self.pty.max_sessions == 0
+            || self.pty.max_sessions_per_principal == 0
+            || self.pty.idle_timeout_s == 0
+            || self.pty.scrollback_kb == 0
+        {
+            anyhow::bail!("[pty] limits must be > 0");
+        }
+        let jobs = &self.jobs;
+        if jobs.max_running == 0
+            || jobs.max_running_per_principal == 0
+            || jobs.timeout_s == 0
+            || jobs.max_output_kb == 0
+            || jobs.retention_s == 0
+            || jobs.max_retained == 0
+        {
+            anyhow::bail!("[jobs] limits must be > 0");
+        }
+        for (name, svc) in &self.services {
+            if svc.cwd.as_deref().is_some_and(|p| !is_plain_relative(p)) {
+                anyhow::bail!("[services.{name}] cwd must be relative to root_dir without `..`");
+            }
+            if svc.ready_port.is_some() && svc.ready_log.is_some() {
+                anyhow::bail!("[services.{name}] set ready_port or ready_log, not both");
+            }
+            if let Some(Err(e)) = svc.ready_log.as_deref().map(regex::bytes::Regex::new) {
+                anyhow::bail!("[services.{name}] invalid ready_log pattern: {e}");
+            }
+            if svc.ready_timeout_s == 0 || svc.log_kb == 0 {
+                anyhow::bail!("[services.{name}] ready_timeout_s and log_kb must be > 0");
+            }
+        }
+        let lockout = &self.auth.lockout;
+        if lockout.enabled && (lockout.max_failures == 0 || lockout.ban_s == 0) {
+            anyhow::bail!("[auth.lockout] max_failures and ban_s must be > 0");
+        }
+        if let Some(a) = &self.approvals {
+            if self.admin.is_none() {
+                anyhow::bail!("approvals need an [admin] socket to approve calls");
+            }
+            if a.timeout_s == 0 {
+                anyhow::bail!("approvals timeout_s must be > 0");
+            }
+        }
+        if let Some(tls) = &self.server.tls {
+            for p in [&tls.cert, &tls.key] {
+                if !p.is_file() {
+                    anyhow::bail!("tls file does not exist: {}", p.display());
+                }
+            }
+            if tls.require_client_cert && tls.client_ca.is_none() {
+                anyhow::bail!("require_client_cert needs client_ca");
+            }
+            if tls.reload_interval_s == 0 {
+                anyhow::bail!("reload_interval_s must be > 0");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Landlock paths must keep the config file and the user's home out of
+    /// reach of exec children.
+    fn validate_landlock(&self, landlock: &Landlock) -> anyhow::Result<()> {
+        if !landlock.enabled {
+            return Ok(());
+        }
+        if let Some(p) = landlock.root_write.iter().find(|p| !is_plain_relative(p)) {
+            anyhow::bail!(
+                "landlock root_write must be relative to root_dir without `..`: {}",
+                p.display()
+            );
+        }
+        let canon = |p: &Path| dunce::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        let outside: Vec<PathBuf> = landlock
+            .read
+            .iter()
+            .chain(&landlock.write)
+            .map(|p| canon(p))
+            .collect();
+        let root = canon(&self.root.root_dir);
+        if let Some(src) = &self.source {
+            if let Some(p) = outside.iter().chain([&root]).find(|p| src.starts_with(p)) {
+                anyhow::bail!(
+                    "config file {} is reachable by exec children through landlock path {}",
+                    src.display(),
+                    p.display()
+                );
+            }
+        }
+        if let Some(home) = std::env::var_os("HOME").map(|h| canon(Path::new(&h))) {
+            if let Some(p) = outside.iter().find(|p| home.starts_with(p)) {
+                anyhow::bail!(
+                    "landlock path {} would expose the home directory {}",
+                    p.display(),
+                    home.display()
+                );
+            }
+            // dotfiles and keys live here; only root_dir itself may sit inside
+            if let Some(p) = outside
+                .iter()
+                .find(|p| p.starts_with(&home) && !p.starts_with(&root))
+            {
+                anyhow::bail!(
+                    "landlock path {} is inside the home directory {}",
+                    p.display(),
+                    home.display()
+                );
+            }
+        }
         Ok(())
     }
 }
+
+/// Relative, with no `..` or root components.
+fn is_plain_relative(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
@@ -1,4 +1,7 @@
-use axum::{http::StatusCode, Json};
+use axum::{
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    Json,
+};
 use serde::Serialize;
 use thiserror::Error;
 
@@ -6,24 +9,58 @@ use thiserror::Error;
 pub enum AppError {
     #[error("unauthorized")]
     Unauthorized,
+    #[error("request timestamp outside allowed window")]
+    RequestExpired,
+    #[error("nonce already used")]
+    NonceReused,
+    #[error("too many failed attempts; retry after {retry_after_s}s")]
+    Banned { retry_after_s: u64 },
     #[error("origin denied")]
     OriginDenied,
+    #[error("client address denied")]
+    IpDenied,
+    #[error("tool not permitted for principal")]
+    ScopeDenied,
     #[error("request too large")]
     RequestTooLarge,
+    #[error("rate limited; retry after {retry_after_s}s")]
+    RateLimited { retry_after_s: u64 },
     #[error("path outside root")]
     PathOutsideRoot,
+    #[error("path denied by policy")]
+    PathDenied,
+    #[error("symlink not allowed")]
+    SymlinkDenied,
+    #[error("missing {0} permission for path")]
+    PermissionDenied(&'static str),
     #[error("not found")]
     NotFound,
     #[error("exec denied")]
     ExecDenied,
+    #[error("binary does not match its pinned sha256")]
+    BinaryMismatch,
+    #[error("argument denied by {0}")]
+    ArgDenied(String),
     #[error("exec timeout")]
     ExecTimeout,
+    #[error("exec queue full")]
+    QueueFull,
+    #[error("timed out waiting in exec queue")]
+    QueueTimeout,
+    #[error("usage budget exceeded: {0}")]
+    BudgetExceeded(String),
+    #[error("denied by operator: {0}")]
+    ApprovalDenied(String),
+    #[error("timed out waiting for operator approval")]
+    ApprovalTimeout,
     #[error("tool error: {0}")]
     ToolError(String),
     #[error("internal error: {0}")]
     Internal(String),
 }
 
+pub type RetryAfter = Option<[(HeaderName, HeaderValue); 1]>;
+
 #[derive(Debug, Serialize)]
 pub struct ErrorBody<'a> {
     pub code: &'a str,
@@ -34,12 +71,28 @@ impl AppError {
     pub fn code(&self) -> &'static str {
         match self {
             AppError::Unauthorized => "Unauthorized",
+            AppError::RequestExpired => "RequestExpired",
+            AppError::NonceReused => "NonceReused",
+            AppError::Banned { .. } => "Banned",
             AppError::OriginDenied => "OriginDenied",
+            AppError::IpDenied => "IpDenied",
+            AppError::ScopeDenied => "ScopeDenied",
             AppError::RequestTooLarge => "RequestTooLarge",
+            AppError::RateLimited { .. } => "RateLimited",
             AppError::PathOutsideRoot => "PathOutsideRoot",
+            AppError::PathDenied => "PathDenied",
+            AppError::SymlinkDenied => "SymlinkDenied",
+            AppError::PermissionDenied(_) => "PermissionDenied",
             AppError::NotFound => "NotFound",
             AppError::ExecDenied => "ExecDenied",
+            AppError::BinaryMismatch => "BinaryMismatch",
+            AppError::ArgDenied(_) => "ArgDenied",
             AppError::ExecTimeout => "ExecTimeout",
+            AppError::QueueFull => "QueueFull",
+            AppError::QueueTimeout => "QueueTimeout",
+            AppError::BudgetExceeded(_) => "BudgetExceeded",
+            AppError::ApprovalDenied(_) => "ApprovalDenied",
+            AppError::ApprovalTimeout => "ApprovalTimeout",
             AppError::ToolError(_) => "ToolError",
             AppError::Internal(_) => "Internal",
         }
@@ -47,24 +100,59 @@ impl AppError {
 
     pub fn status(&self) -> StatusCode {
         match self {
-            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
-            AppError::OriginDenied | AppError::PathOutsideRoot | AppError::ExecDenied => {
-                StatusCode::FORBIDDEN
+            AppError::Unauthorized | AppError::RequestExpired | AppError::NonceReused => {
+                StatusCode::UNAUTHORIZED
             }
+            AppError::OriginDenied
+            | AppError::IpDenied
+            | AppError::ScopeDenied
+            | AppError::PathOutsideRoot
+            | AppError::PathDenied
+            | AppError::SymlinkDenied
+            | AppError::PermissionDenied(_)
+            | AppError::ExecDenied
+            | AppError::BinaryMismatch
+            | AppError::ArgDenied(_)
+            | AppError::ApprovalDenied(_) => StatusCode::FORBIDDEN,
             AppError::RequestTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::RateLimited { .. }
+            | AppError::Banned { .. }
+            | AppError::BudgetExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
             AppError::NotFound => StatusCode::NOT_FOUND,
-            AppError::ExecTimeout => StatusCode::REQUEST_TIMEOUT,
+            AppError::ExecTimeout | AppError::ApprovalTimeout => StatusCode::REQUEST_TIMEOUT,
+            AppError::QueueFull | AppError::QueueTimeout => StatusCode::SERVICE_UNAVAILABLE,
             AppError::ToolError(_) => StatusCode::BAD_REQUEST,
             AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-pub fn into_response(err: AppError) -> (StatusCode, Json<ErrorBody<'static>>) {
+impl AppError {
+    /// The policy rule behind a denial, for the audit log.
+    pub fn denied_by(&self) -> Option<&str> {
+        match self {
+            AppError::ArgDenied(rule) => Some(rule),
+            _ => None,
+        }
+    }
+
+    /// `Retry-After` header for errors the client should back off from.
+    pub fn retry_after(&self) -> RetryAfter {
+        match self {
+            AppError::RateLimited { retry_after_s } | AppError::Banned { retry_after_s } => {
+                Some([(header::RETRY_AFTER, HeaderValue::from(*retry_after_s))])
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn into_response(err: AppError) -> (StatusCode, RetryAfter, Json<ErrorBody<'static>>) {
     let code = err.code();
     let message = err.to_string();
     (
         err.status(),
+        err.retry_after(),
         Json(ErrorBody {
             code,
             message: Box::leak(message.into_boxed_str()),
@@ -0,0 +1,156 @@
+use crate::{config::LockoutPolicy, errors::AppError};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Failed authentication attempts per client address and route. Reaching
+/// `max_failures` within `window_s` bans the client, doubling the ban each time.
+#[derive(Debug)]
+pub struct Lockout {
+    policy: LockoutPolicy,
+    entries: Mutex<HashMap<(IpAddr, String), Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    failures: u32,
+    window_start: Instant,
+    strikes: u32,
+    banned_until: Option<Instant>,
+    last_failure: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ban {
+    pub ip: IpAddr,
+    pub prefix: String,
+    pub strikes: u32,
+    pub remaining_s: u64,
+}
+
+impl Lockout {
+    pub fn new(policy: &LockoutPolicy) -> Self {
+        Self {
+            policy: policy.clone(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `forwarded` means the request came through a proxy the client address
+    /// couldn't be resolved past (e.g. Funnel without `trusted_proxies`); the
+    /// proxy's loopback address then stands for remote clients and is not exempt.
+    fn tracked(&self, ip: Option<IpAddr>, forwarded: bool) -> Option<IpAddr> {
+        let ip = ip?;
+        let exempt = !forwarded && self.policy.exempt.iter().any(|n: &IpNet| n.contains(&ip));
+        (self.policy.enabled && !exempt).then_some(ip)
+    }
+
+    pub fn check(
+        &self,
+        ip: Option<IpAddr>,
+        forwarded: bool,
+        prefix: &str,
+        now: Instant,
+    ) -> Result<(), AppError> {
+        let Some(ip) = self.tracked(ip, forwarded) else {
+            return Ok(());
+        };
+        let entries = self.entries.lock().unwrap();
+        match entries
+            .get(&(ip, prefix.to_string()))
+            .and_then(|e| e.banned_until)
+        {
+            Some(until) if until > now => Err(AppError::Banned {
+                retry_after_s: until.duration_since(now).as_secs_f64().ceil() as u64,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts a failure; returns the ban length if this one triggered a ban.
+    pub fn record_failure(
+        &self,
+        ip: Option<IpAddr>,
+        forwarded: bool,
+        prefix: &str,
+        now: Instant,
+    ) -> Option<Duration> {
+        let ip = self.tracked(ip, forwarded)?;
+        let mut entries = self.entries.lock().unwrap();
+        let e = entries
+            .entry((ip, prefix.to_string()))
+            .or_insert_with(|| Entry {
+                failures: 0,
+                window_start: now,
+                strikes: 0,
+                banned_until: None,
+                last_failure: now,
+            });
+        if now.duration_since(e.window_start) > Duration::from_secs(self.policy.window_s) {
+            e.failures = 0;
+            e.window_start = now;
+        }
+        e.failures += 1;
+        e.last_failure = now;
+        if e.failures < self.policy.max_failures {
+            return None;
+        }
+        e.strikes += 1;
+        e.failures = 0;
+        let ban_s = self
+            .policy
+            .ban_s
+            .saturating_mul(1u64 << (e.strikes - 1).min(32))
+            .min(self.policy.max_ban_s);
+        let ban = Duration::from_secs(ban_s);
+        e.banned_until = Some(now + ban);
+        Some(ban)
+    }
+
+    pub fn record_success(&self, ip: Option<IpAddr>, forwarded: bool, prefix: &str) {
+        if let Some(ip) = self.tracked(ip, forwarded) {
+            self.entries
+                .lock()
+                .unwrap()
+                .remove(&(ip, prefix.to_string()));
+        }
+    }
+
+    pub fn bans(&self, now: Instant) -> Vec<Ban> {
+        let entries = self.entries.lock().unwrap();
+        let mut bans: Vec<_> = entries
+            .iter()
+            .filter_map(|((ip, prefix), e)| {
+                let until = e.banned_until.filter(|u| *u > now)?;
+                Some(Ban {
+                    ip: *ip,
+                    prefix: prefix.clone(),
+                    strikes: e.strikes,
+                    remaining_s: until.duration_since(now).as_secs(),
+                })
+            })
+            .collect();
+        bans.sort_by_key(|b| (b.ip, b.prefix.clone()));
+        bans
+    }
+
+    /// Forgets failures and bans for `ip`, or for everyone. Returns how many entries went.
+    pub fn clear(&self, ip: Option<IpAddr>) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|(entry_ip, _), _| ip.is_some_and(|ip| ip != *entry_ip));
+        before - entries.len()
+    }
+
+    /// Drops entries whose ban ended and that have been quiet for `max_ban_s`,
+    /// which also resets their backoff.
+    pub fn retain_recent(&self, now: Instant) {
+        let quiet = Duration::from_secs(self.policy.max_ban_s.max(self.policy.window_s));
+        self.entries.lock().unwrap().retain(|_, e| {
+            e.banned_until.is_some_and(|u| u > now) || now.duration_since(e.last_failure) < quiet
+        });
+    }
+}
@@ -1,10 +1,39 @@
+use crate::redact::Redactor;
+use std::io::{self, Write};
+use std::sync::{Arc, OnceLock};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+/// Set once the config is loaded; lines logged before that are written as-is.
+static REDACTOR: OnceLock<Arc<Redactor>> = OnceLock::new();
+
 pub fn init() {
-    let fmt_layer = fmt::layer().json();
+    let fmt_layer = fmt::layer().json().with_writer(|| RedactingStdout);
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     tracing_subscriber::registry()
         .with(filter)
         .with(fmt_layer)
         .init();
 }
+
+pub fn set_redactor(redactor: Arc<Redactor>) {
+    let _ = REDACTOR.set(redactor);
+}
+
+/// Stdout writer that redacts each formatted event before it is written.
+struct RedactingStdout;
+
+impl Write for RedactingStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match REDACTOR.get() {
+            Some(r) => {
+                io::stdout().write_all(&r.redact(buf).0)?;
+                Ok(buf.len())
+            }
+            None => io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
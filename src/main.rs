@@ -1,9 +1,19 @@
+mod admin;
+mod approvals;
+mod budget;
+mod cli;
 mod config;
 mod errors;
+mod lockout;
 mod logging;
 mod mcp;
+mod redact;
 mod security;
 mod server;
+mod tailscale;
+#[cfg(test)]
+mod tests;
+mod tls;
 mod tools;
 
 use crate::config::Config;
@@ -17,6 +27,7 @@ async fn main() -> anyhow::Result<()> {
 
     let args: Vec<String> = std::env::args().collect();
     let mut config_path = PathBuf::from("valet.toml");
+    let mut command = Vec::new();
     let mut i = 1;
     while i < args.len() {
         if args[i].as_str() == "--config" {
@@ -26,12 +37,18 @@ async fn main() -> anyhow::Result<()> {
                 std::process::exit(2);
             }
             config_path = PathBuf::from(&args[i]);
+        } else {
+            command.push(args[i].clone());
         }
         i += 1;
     }
 
     let cfg = Config::load(&config_path).context("loading config")?;
     cfg.validate().context("validating config")?;
+    logging::set_redactor(std::sync::Arc::new(redact::Redactor::new(&cfg)?));
+    if !command.is_empty() {
+        return cli::run(&cfg, &command).await;
+    }
 
     let addr = format!("{}:{}", cfg.server.bind_addr, cfg.server.port);
 
@@ -46,5 +63,27 @@ async fn main() -> anyhow::Result<()> {
         registry.list_names().join(",")
     );
 
-    server::serve(cfg, registry).await
+    let services = registry.services();
+    tokio::select! {
+        r = server::serve(cfg, registry) => r,
+        _ = shutdown_signal() => {
+            info!("shutting down");
+            // services die with us regardless; this gives them a clean exit
+            services.stop_all().await;
+            Ok(())
+        }
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut term = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(_) => return std::future::pending().await,
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
 }
@@ -1,25 +1,129 @@
-use crate::{config::Config, errors::AppError};
+use crate::{
+    budget::{BudgetTracker, Usage},
+    config::Config,
+    errors::AppError,
+    security::Principal,
+    tools::{queue::ExecQueue, services::Services},
+};
 use async_trait::async_trait;
 use std::sync::Arc;
 
 pub type DynTool = Arc<dyn Tool + Send + Sync + 'static>;
 
+/// Tools that start an allowlisted command. Approval rules and
+/// `[limits.rate.per_tool]` entries for `exec` apply to all of them.
+const RUNS_COMMANDS: &[&str] = &["exec", "job_start", "pty_open"];
+
+/// Whether `tool` starts an allowlisted command and so counts as `exec`.
+pub fn runs_commands(tool: &str) -> bool {
+    RUNS_COMMANDS.contains(&tool)
+}
+
 #[derive(Clone)]
 pub struct ToolRegistry {
     tools: Vec<(String, DynTool)>,
+    exec_queue: Arc<ExecQueue>,
+    services: Arc<Services>,
+    budgets: Option<Arc<BudgetTracker>>,
+}
+
+/// Per-call facts tools may use for policy and accounting.
+#[derive(Debug, Clone)]
+pub struct CallContext {
+    pub principal: Principal,
+    pub budgets: Option<Arc<BudgetTracker>>,
+}
+
+impl CallContext {
+    pub fn new(principal: Principal) -> Self {
+        Self {
+            principal,
+            budgets: None,
+        }
+    }
+
+    pub fn with_budgets(mut self, budgets: Option<Arc<BudgetTracker>>) -> Self {
+        self.budgets = budgets;
+        self
+    }
+
+    /// Charges resources used by this call to the principal's budget, if any.
+    pub fn record_usage(&self, usage: Usage) {
+        if let Some(b) = &self.budgets {
+            b.record(&self.principal.name, usage);
+        }
+    }
 }
 
 impl ToolRegistry {
     pub fn new(cfg: &Config) -> anyhow::Result<Self> {
-        use crate::tools::{exec::ExecTool, fs_read::FsReadTool, fs_write::FsWriteTool};
-        let exec = ExecTool::new(cfg)?;
+        use crate::tools::{
+            budget_status::BudgetStatusTool,
+            exec::ExecTool,
+            exec_stdin::ExecStdinTool,
+            fs_list::FsListTool,
+            fs_read::FsReadTool,
+            fs_write::FsWriteTool,
+            jobs::{JobOp, JobTool, Jobs},
+            pty::{PtyOp, PtySessions, PtyTool},
+            services::{ServiceOp, ServiceTool, Services},
+        };
+        let exec = Arc::new(ExecTool::new(cfg)?);
+        let exec_queue = exec.queue();
+        let exec_stdin = ExecStdinTool::new(exec.stdin_streams());
+        let ptys = Arc::new(PtySessions::new(cfg, exec.clone())?);
+        let pty = |op| -> DynTool { Arc::new(PtyTool::new(ptys.clone(), op)) };
+        let jobs = Arc::new(Jobs::new(cfg, exec.clone())?);
+        let job = |op| -> DynTool { Arc::new(JobTool::new(jobs.clone(), op)) };
+        let services = Arc::new(Services::new(cfg, exec.clone())?);
+        let service = |op| -> DynTool { Arc::new(ServiceTool::new(services.clone(), op)) };
         let mut tools: Vec<(String, DynTool)> = vec![
+            ("fs_list".to_string(), Arc::new(FsListTool::new(cfg)?)),
             ("fs_read".to_string(), Arc::new(FsReadTool::new(cfg)?)),
             ("fs_write".to_string(), Arc::new(FsWriteTool::new(cfg)?)),
-            ("exec".to_string(), Arc::new(exec)),
+            ("exec".to_string(), exec),
+            ("exec_stdin".to_string(), Arc::new(exec_stdin)),
+            ("job_start".to_string(), job(JobOp::Start)),
+            ("job_status".to_string(), job(JobOp::Status)),
+            ("job_output".to_string(), job(JobOp::Output)),
+            ("job_wait".to_string(), job(JobOp::Wait)),
+            ("job_kill".to_string(), job(JobOp::Kill)),
+            ("pty_open".to_string(), pty(PtyOp::Open)),
+            ("pty_write".to_string(), pty(PtyOp::Write)),
+            ("pty_read".to_string(), pty(PtyOp::Read)),
+            ("pty_resize".to_string(), pty(PtyOp::Resize)),
+            ("pty_close".to_string(), pty(PtyOp::Close)),
+            ("service_start".to_string(), service(ServiceOp::Start)),
+            ("service_stop".to_string(), service(ServiceOp::Stop)),
+            ("service_status".to_string(), service(ServiceOp::Status)),
+            ("service_logs".to_string(), service(ServiceOp::Logs)),
         ];
+        let budgets = match &cfg.budgets {
+            Some(b) => {
+                tools.push(("budget_status".to_string(), Arc::new(BudgetStatusTool)));
+                Some(Arc::new(BudgetTracker::load(b)?))
+            }
+            None => None,
+        };
         tools.sort_by(|a, b| a.0.cmp(&b.0));
-        Ok(Self { tools })
+        Ok(Self {
+            tools,
+            exec_queue,
+            services,
+            budgets,
+        })
+    }
+
+    pub fn exec_queue(&self) -> &ExecQueue {
+        &self.exec_queue
+    }
+
+    pub fn services(&self) -> Arc<Services> {
+        self.services.clone()
+    }
+
+    pub fn budgets(&self) -> Option<Arc<BudgetTracker>> {
+        self.budgets.clone()
     }
 
     pub fn get(&self, name: &str) -> Option<DynTool> {
@@ -36,9 +140,14 @@ impl ToolRegistry {
 #[async_trait]
 pub trait Tool {
     fn capabilities(&self) -> serde_json::Value;
-    async fn call(&self, params: serde_json::Value) -> Result<serde_json::Value, AppError>;
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError>;
     async fn call_stream(
         &self,
+        _ctx: &CallContext,
         _params: serde_json::Value,
     ) -> Result<crate::server::StreamBody, AppError> {
         Err(AppError::ToolError("streaming not supported".into()))
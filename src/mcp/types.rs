@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub struct Capabilities {
     pub mcp_version: &'static str,
@@ -7,6 +8,7 @@ pub struct Capabilities {
     pub streaming: bool,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub struct ToolInfo {
     pub name: String,
@@ -14,6 +16,7 @@ pub struct ToolInfo {
     pub output_schema: serde_json::Value,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorObj {
     pub code: String,
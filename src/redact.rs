@@ -0,0 +1,145 @@
+use crate::config::Config;
+use regex::bytes::Regex;
+use std::sync::Arc;
+
+/// Built-in detectors. Private key blocks also match when cut off (truncated
+/// output), so a partial key is never returned.
+const BUILTIN: &[&str] = &[
+    // AWS access key ids and secret keys in assignments
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    r#"(?i)aws_secret_access_key["']?\s*[:=]\s*["']?[A-Za-z0-9/+=]{40}"#,
+    // GitHub classic and fine-grained tokens
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+    r"\bgithub_pat_[A-Za-z0-9_]{22,}\b",
+    r"(?s)-----BEGIN [A-Z ]*PRIVATE KEY-----.*?(?:-----END [A-Z ]*PRIVATE KEY-----|\z)",
+    // JWTs: base64url header and payload starting with `{"`
+    r"\beyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*",
+];
+
+/// Longest partial line held back while streaming before it is flushed anyway.
+const LINE_HOLD: usize = 512;
+/// Longest unterminated private key block held back while streaming.
+const BLOCK_HOLD: usize = 16 * 1024;
+
+/// Replaces secrets in tool output and log lines.
+#[derive(Debug)]
+pub struct Redactor {
+    pattern: Option<Regex>,
+    key_start: Regex,
+    replacement: Vec<u8>,
+}
+
+impl Redactor {
+    pub fn new(cfg: &Config) -> anyhow::Result<Self> {
+        let r = &cfg.redaction;
+        let mut patterns: Vec<String> = vec![];
+        if r.enabled {
+            if r.builtin {
+                patterns.extend(BUILTIN.iter().map(|p| p.to_string()));
+            }
+            // our own credentials never leave through a tool; very short test
+            // tokens would mangle ordinary output, so they are skipped
+            let secrets = [
+                Some(&cfg.auth.bearer_token),
+                cfg.auth.hmac.as_ref().map(|h| &h.key),
+            ];
+            for s in secrets.into_iter().flatten().filter(|s| s.len() >= 8) {
+                patterns.push(regex::escape(s));
+            }
+            patterns.extend(r.patterns.iter().cloned());
+        }
+        let pattern = if patterns.is_empty() {
+            None
+        } else {
+            let joined = patterns
+                .iter()
+                .map(|p| format!("(?:{p})"))
+                .collect::<Vec<_>>()
+                .join("|");
+            Some(Regex::new(&joined)?)
+        };
+        Ok(Self {
+            pattern,
+            key_start: Regex::new(r"-----BEGIN [A-Z ]*PRIVATE KEY-----")?,
+            replacement: r.replacement.clone().into_bytes(),
+        })
+    }
+
+    /// Returns the redacted text and the number of replacements.
+    pub fn redact(&self, input: &[u8]) -> (Vec<u8>, usize) {
+        let Some(re) = &self.pattern else {
+            return (input.to_vec(), 0);
+        };
+        let mut count = 0;
+        let out = re.replace_all(input, |_: &regex::bytes::Captures| {
+            count += 1;
+            self.replacement.clone()
+        });
+        (out.into_owned(), count)
+    }
+
+    pub fn stream(self: &Arc<Self>) -> StreamRedactor {
+        StreamRedactor {
+            redactor: self.clone(),
+            buf: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// How much of a streaming buffer can be redacted and sent without cutting
+    /// a secret in half: complete lines, minus any unterminated key block.
+    fn safe_len(&self, buf: &[u8]) -> usize {
+        let Some(re) = &self.pattern else {
+            return buf.len();
+        };
+        let mut safe = match buf.iter().rposition(|b| *b == b'\n') {
+            Some(p) => p + 1,
+            None => 0,
+        };
+        safe = safe.max(buf.len().saturating_sub(LINE_HOLD));
+        if let Some(m) = re
+            .find_iter(buf)
+            .find(|m| m.start() < safe && m.end() > safe)
+        {
+            safe = m.start();
+        }
+        if let Some(start) = self.key_start.find_iter(buf).last().map(|m| m.start()) {
+            let ended = buf[start..].windows(9).any(|w| w == b"-----END ");
+            if !ended && buf.len() - start <= BLOCK_HOLD {
+                safe = safe.min(start);
+            }
+        }
+        safe
+    }
+}
+
+/// Redacts a byte stream chunk by chunk, holding back just enough to catch
+/// secrets split across chunks.
+pub struct StreamRedactor {
+    redactor: Arc<Redactor>,
+    buf: Vec<u8>,
+    pub count: usize,
+}
+
+impl StreamRedactor {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.buf.extend_from_slice(chunk);
+        let cut = self.redactor.safe_len(&self.buf);
+        let rest = self.buf.split_off(cut);
+        let ready = std::mem::replace(&mut self.buf, rest);
+        let (out, n) = self.redactor.redact(&ready);
+        self.count += n;
+        out
+    }
+
+    /// Whether a tail is being held back for the next chunk.
+    pub fn holding(&self) -> bool {
+        !self.buf.is_empty()
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        let (out, n) = self.redactor.redact(&std::mem::take(&mut self.buf));
+        self.count += n;
+        out
+    }
+}
@@ -1,12 +1,38 @@
-use crate::errors::AppError;
+use crate::{
+    config::{Hmac, OriginPolicy, Rate, RateLimits},
+    errors::AppError,
+    mcp::registry::runs_commands,
+};
 use axum::http::HeaderMap;
 use governor::{
-    clock::DefaultClock,
+    clock::{Clock, DefaultClock},
     state::{keyed::DefaultKeyedStateStore, InMemoryState, NotKeyed},
-    Quota, RateLimiter,
+    NotUntil, Quota, RateLimiter,
 };
-use std::num::NonZeroU32;
-use std::sync::Arc;
+use ipnet::IpNet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+/// Identity a request is attributed to, with the tools it may call.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl Principal {
+    pub fn unrestricted(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            scopes: vec!["*".to_string()],
+        }
+    }
+
+    pub fn allows_tool(&self, tool: &str) -> bool {
+        self.scopes.iter().any(|s| s == "*" || s == tool)
+    }
+}
 
 #[allow(dead_code)]
 pub fn require_bearer(headers: &HeaderMap, expected: &str) -> Result<(), AppError> {
@@ -24,26 +50,219 @@ pub fn require_bearer(headers: &HeaderMap, expected: &str) -> Result<(), AppErro
     Ok(())
 }
 
-pub fn extract_bearer(headers: &HeaderMap) -> Option<String> {
-    headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.strip_prefix("Bearer "))
-        .map(|s| s.to_string())
-}
-
 pub fn check_origin(headers: &HeaderMap, allowed: &[String]) -> Result<(), AppError> {
     let origin = headers
         .get("Origin")
         .and_then(|v| v.to_str().ok())
         .ok_or(AppError::OriginDenied)?;
-    if allowed.iter().any(|o| o == origin) {
+    if origin_allowed(origin, allowed) {
         Ok(())
     } else {
         Err(AppError::OriginDenied)
     }
 }
 
+pub fn check_origin_policy(headers: &HeaderMap, policy: &OriginPolicy) -> Result<(), AppError> {
+    if policy.allow_missing_origin && !headers.contains_key("Origin") {
+        return Ok(());
+    }
+    check_origin(headers, &policy.allowed_origins)
+}
+
+/// Checks signed requests (`X-Valet-Signature`, `X-Valet-Timestamp`, `X-Valet-Nonce`)
+/// and remembers nonces for the skew window so a captured request can't be replayed.
+pub struct RequestVerifier {
+    key: ring::hmac::Key,
+    max_skew_s: u64,
+    capacity: usize,
+    pub principal: String,
+    nonces: Mutex<NonceCache>,
+}
+
+#[derive(Default)]
+struct NonceCache {
+    seen: HashSet<String>,
+    order: VecDeque<(u64, String)>,
+}
+
+impl RequestVerifier {
+    pub fn new(cfg: &Hmac) -> Self {
+        Self {
+            key: ring::hmac::Key::new(ring::hmac::HMAC_SHA256, cfg.key.as_bytes()),
+            max_skew_s: cfg.max_skew_s,
+            capacity: cfg.nonce_cache,
+            principal: cfg.principal.clone(),
+            nonces: Mutex::new(NonceCache::default()),
+        }
+    }
+
+    /// `now` is the current unix time in seconds.
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8], now: u64) -> Result<(), AppError> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .ok_or(AppError::Unauthorized)
+        };
+        let signature =
+            hex::decode(header("x-valet-signature")?.trim()).map_err(|_| AppError::Unauthorized)?;
+        let timestamp = header("x-valet-timestamp")?;
+        let nonce = header("x-valet-nonce")?;
+        if nonce.len() < 16 || nonce.len() > 128 {
+            return Err(AppError::Unauthorized);
+        }
+        let ts: u64 = timestamp.parse().map_err(|_| AppError::Unauthorized)?;
+        let mut message = format!("{timestamp}\n{nonce}\n").into_bytes();
+        message.extend_from_slice(body);
+        ring::hmac::verify(&self.key, &message, &signature).map_err(|_| AppError::Unauthorized)?;
+        if ts.abs_diff(now) > self.max_skew_s {
+            return Err(AppError::RequestExpired);
+        }
+
+        let mut cache = self.nonces.lock().unwrap();
+        // a nonce older than twice the window can't pass the timestamp check again
+        while let Some((seen_at, _)) = cache.order.front() {
+            if seen_at + 2 * self.max_skew_s >= now {
+                break;
+            }
+            let (_, old) = cache.order.pop_front().unwrap();
+            cache.seen.remove(&old);
+        }
+        if cache.seen.contains(nonce) {
+            return Err(AppError::NonceReused);
+        }
+        if cache.order.len() >= self.capacity {
+            let oldest = cache.order.front().map(|(t, _)| *t).unwrap_or(now);
+            return Err(AppError::RateLimited {
+                retry_after_s: (oldest + 2 * self.max_skew_s).saturating_sub(now).max(1),
+            });
+        }
+        cache.seen.insert(nonce.to_string());
+        cache.order.push_back((now, nonce.to_string()));
+        Ok(())
+    }
+}
+
+pub fn origin_allowed(origin: &str, allowed: &[String]) -> bool {
+    allowed.iter().any(|p| origin_matches(p, origin))
+}
+
+/// Matches an exact origin (including the literal `null`) or a `scheme://*.domain[:port]`
+/// pattern, which requires at least one subdomain label.
+pub fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == origin {
+        return true;
+    }
+    let Some((scheme, suffix)) = pattern.split_once("://*.") else {
+        return false;
+    };
+    let Some(host) = origin
+        .strip_prefix(scheme)
+        .and_then(|o| o.strip_prefix("://"))
+    else {
+        return false;
+    };
+    let Some(labels) = host
+        .strip_suffix(suffix)
+        .and_then(|h| h.strip_suffix('.'))
+    else {
+        return false;
+    };
+    !labels.is_empty()
+        && labels
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+pub fn valid_origin_pattern(pattern: &str) -> bool {
+    if pattern == "null" {
+        return true;
+    }
+    let Some((scheme, rest)) = pattern.split_once("://") else {
+        return false;
+    };
+    let host = rest.strip_prefix("*.").unwrap_or(rest);
+    !scheme.is_empty() && !host.is_empty() && !host.contains(['*', '/'])
+}
+
+/// Resolves the real client address. Forwarding headers are only honoured when the
+/// immediate peer is a trusted proxy; the chain is then walked right to left and the
+/// first hop that is not itself a trusted proxy is the client.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let peer = peer.to_canonical();
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|n| n.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+    let chain = forwarded_chain(headers);
+    chain
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(ip))
+        .or(chain.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+/// Whether an untrusted peer forwarded the request for someone else, so the
+/// peer's own address stands in for the client (e.g. Funnel without
+/// `trusted_proxies`).
+pub fn is_forwarded(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> bool {
+    let peer = peer.to_canonical();
+    !trusted.iter().any(|n| n.contains(&peer)) && !forwarded_chain(headers).is_empty()
+}
+
+/// Client chain from `Forwarded` (RFC 7239) or, if absent, `X-Forwarded-For`, oldest first.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<IpAddr> {
+    let values = |name: &str| -> Vec<String> {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|s| s.trim().to_string())
+            .collect()
+    };
+    let forwarded: Vec<IpAddr> = values("forwarded")
+        .iter()
+        .filter_map(|elem| {
+            elem.split(';')
+                .filter_map(|kv| kv.trim().split_once('='))
+                .find(|(k, _)| k.eq_ignore_ascii_case("for"))
+                .and_then(|(_, v)| parse_node(v.trim_matches('"')))
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    values("x-forwarded-for")
+        .iter()
+        .filter_map(|s| parse_node(s))
+        .collect()
+}
+
+fn parse_node(s: &str) -> Option<IpAddr> {
+    s.parse::<IpAddr>()
+        .ok()
+        .or_else(|| s.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        .or_else(|| {
+            s.strip_prefix('[')
+                .and_then(|r| r.split_once(']'))
+                .and_then(|(ip, _)| ip.parse().ok())
+        })
+        .map(|ip| ip.to_canonical())
+}
+
+pub fn check_cidrs(ip: Option<IpAddr>, allowed: &[IpNet]) -> Result<(), AppError> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+    match ip {
+        Some(ip) if allowed.iter().any(|n| n.contains(&ip)) => Ok(()),
+        _ => Err(AppError::IpDenied),
+    }
+}
+
 pub fn content_length_ok(headers: &HeaderMap, max_kb: usize) -> Result<(), AppError> {
     if let Some(len) = headers
         .get(axum::http::header::CONTENT_LENGTH)
@@ -57,38 +276,71 @@ pub fn content_length_ok(headers: &HeaderMap, max_kb: usize) -> Result<(), AppEr
     Ok(())
 }
 
+type KeyedLimiter<K> = RateLimiter<K, DefaultKeyedStateStore<K>, DefaultClock>;
+
 #[derive(Clone)]
 pub struct RateLimiters {
     pub global: Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
-    pub per_token: Arc<RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock>>,
+    pub per_principal: Arc<KeyedLimiter<String>>,
+    pub per_ip: Arc<KeyedLimiter<IpAddr>>,
+    pub per_tool: Arc<HashMap<String, KeyedLimiter<String>>>,
+}
+
+fn quota(rate: &Rate) -> Quota {
+    Quota::per_second(rate.per_sec).allow_burst(rate.burst)
+}
+
+fn limited(not_until: NotUntil<<DefaultClock as Clock>::Instant>) -> AppError {
+    let wait = not_until.wait_time_from(DefaultClock::default().now());
+    AppError::RateLimited {
+        retry_after_s: wait.as_secs() + u64::from(wait.subsec_nanos() > 0),
+    }
 }
 
 impl RateLimiters {
-    pub fn new(
-        global_per_sec: u32,
-        global_burst: u32,
-        per_token_per_sec: u32,
-        per_token_burst: u32,
-    ) -> Self {
-        let gq = Quota::per_second(NonZeroU32::new(global_per_sec).unwrap())
-            .allow_burst(NonZeroU32::new(global_burst).unwrap());
-        let pq = Quota::per_second(NonZeroU32::new(per_token_per_sec).unwrap())
-            .allow_burst(NonZeroU32::new(per_token_burst).unwrap());
-        let global = RateLimiter::direct(gq);
-        let per_token = RateLimiter::keyed(pq);
+    pub fn new(cfg: &RateLimits) -> Self {
+        let per_tool = cfg
+            .per_tool
+            .iter()
+            .map(|(tool, rate)| (tool.clone(), RateLimiter::keyed(quota(rate))))
+            .collect();
         Self {
-            global: Arc::new(global),
-            per_token: Arc::new(per_token),
+            global: Arc::new(RateLimiter::direct(quota(&cfg.global))),
+            per_principal: Arc::new(RateLimiter::keyed(quota(&cfg.per_principal))),
+            per_ip: Arc::new(RateLimiter::keyed(quota(&cfg.per_ip))),
+            per_tool: Arc::new(per_tool),
+        }
+    }
+
+    /// Applied to every JSON-RPC request once the caller is known.
+    pub fn check(&self, principal: &str, ip: Option<IpAddr>) -> Result<(), AppError> {
+        self.global.check().map_err(limited)?;
+        self.per_principal
+            .check_key(&principal.to_string())
+            .map_err(limited)?;
+        if let Some(ip) = ip {
+            self.per_ip.check_key(&ip).map_err(limited)?;
         }
+        Ok(())
     }
 
-    pub fn check(&self, token: Option<&str>) -> Result<(), AppError> {
-        self.global.check().map_err(|_| AppError::RequestTooLarge)?;
-        if let Some(t) = token {
-            self.per_token
-                .check_key(&t.to_string())
-                .map_err(|_| AppError::RequestTooLarge)?;
+    /// The tool's own limit and, for tools that start commands, the `exec` one.
+    pub fn check_tool(&self, tool: &str, principal: &str) -> Result<(), AppError> {
+        let exec = (tool != "exec" && runs_commands(tool)).then_some("exec");
+        for name in std::iter::once(tool).chain(exec) {
+            if let Some(l) = self.per_tool.get(name) {
+                l.check_key(&principal.to_string()).map_err(limited)?;
+            }
         }
         Ok(())
     }
+
+    /// Drops keyed state that has fully replenished so idle keys don't accumulate.
+    pub fn retain_recent(&self) {
+        self.per_principal.retain_recent();
+        self.per_ip.retain_recent();
+        for l in self.per_tool.values() {
+            l.retain_recent();
+        }
+    }
 }
@@ -1,18 +1,23 @@
 use crate::{
-    config::Config,
+    admin::{self, AdminState},
+    approvals::{ApprovalQueue, PendingApproval},
+    config::{AuthMode, Config, OriginPolicy},
     errors::{into_response, AppError},
-    mcp::registry::ToolRegistry,
-    security,
+    lockout::Lockout,
+    mcp::registry::{CallContext, DynTool, ToolRegistry},
+    security::{self, Principal},
+    tls::{ClientCertificate, TlsReloader},
 };
 use axum::{
-    extract::{Path, State},
+    extract::{ConnectInfo, MatchedPath, Path, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -20,6 +25,36 @@ pub struct AppState {
     pub cfg: Arc<Config>,
     pub registry: Arc<ToolRegistry>,
     pub rls: crate::security::RateLimiters,
+    pub approvals: Option<Arc<ApprovalQueue>>,
+    pub verifier: Option<Arc<security::RequestVerifier>>,
+    pub lockout: Arc<Lockout>,
+}
+
+impl AppState {
+    pub fn new(cfg: Config, registry: ToolRegistry) -> Self {
+        Self {
+            registry: Arc::new(registry),
+            rls: crate::security::RateLimiters::new(&cfg.limits.rate),
+            approvals: cfg
+                .approvals
+                .as_ref()
+                .map(|a| Arc::new(ApprovalQueue::new(a, &cfg.root.root_dir))),
+            verifier: cfg
+                .auth
+                .hmac
+                .as_ref()
+                .map(|h| Arc::new(security::RequestVerifier::new(h))),
+            lockout: Arc::new(Lockout::new(&cfg.auth.lockout)),
+            cfg: Arc::new(cfg),
+        }
+    }
+
+    pub fn admin(&self) -> AdminState {
+        AdminState {
+            approvals: self.approvals.clone(),
+            lockout: self.lockout.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -49,12 +84,36 @@ struct JsonRpcError {
 
 pub type StreamBody = axum::body::Body;
 
+/// Who a request is attributed to in audit lines.
+#[derive(Debug, Clone)]
+struct Caller {
+    principal: Principal,
+    client_ip: Option<IpAddr>,
+}
+
 pub async fn serve(cfg: Config, registry: ToolRegistry) -> anyhow::Result<()> {
-    let shared = AppState {
-        cfg: Arc::new(cfg),
-        registry: Arc::new(registry),
-        rls: crate::security::RateLimiters::new(20, 40, 10, 20),
-    };
+    let shared = AppState::new(cfg, registry);
+
+    let rls = shared.rls.clone();
+    let lockout = shared.lockout.clone();
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            tick.tick().await;
+            rls.retain_recent();
+            lockout.retain_recent(std::time::Instant::now());
+        }
+    });
+
+    if let Some(a) = &shared.cfg.admin {
+        let socket = a.socket.clone();
+        let app = admin::router(shared.admin());
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(&socket, app).await {
+                tracing::error!(error = %e, socket = %socket.display(), "admin socket failed");
+            }
+        });
+    }
 
     let app = build_router(shared.clone());
 
@@ -63,31 +122,36 @@ pub async fn serve(cfg: Config, registry: ToolRegistry) -> anyhow::Result<()> {
             .parse()
             .unwrap();
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    match &shared.cfg.server.tls {
+        Some(tls) => {
+            let reloader = std::sync::Arc::new(TlsReloader::new(tls)?);
+            crate::tls::serve(listener, app, reloader).await?;
+        }
+        None => {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await?
+        }
+    }
     Ok(())
 }
 
 pub fn build_router(shared: AppState) -> Router {
     let base = shared.cfg.server.base_path.clone();
     use tower_http::limit::RequestBodyLimitLayer;
-    use tower_http::cors::{CorsLayer, Any};
-    use axum::http::{HeaderName, Method};
     let limit_bytes = shared.cfg.limits.max_request_kb * 1024;
-    
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(Any)
-        .expose_headers([
-            HeaderName::from_static("mcp-session-id"),
-            HeaderName::from_static("www-authenticate")
-        ]);
-        
-    Router::new()
+
+    let healthz = Router::new()
         .route("/healthz", get(health))
+        .layer(cors_layer(shared.cfg.auth.healthz_origins()));
+    let mcp = Router::new()
         .route(
             &base,
-            get(mcp_root_handler),
+            post(mcp_handler)
+                .get(mcp_get_handler)
+                .layer(RequestBodyLimitLayer::new(limit_bytes)),
         )
         .route(
             &format!("{base}/:token"),
@@ -95,13 +159,41 @@ pub fn build_router(shared: AppState) -> Router {
                 .get(mcp_get_handler)
                 .layer(RequestBodyLimitLayer::new(limit_bytes)),
         )
-        .layer(cors)
-        .with_state(shared)
+        .layer(cors_layer(shared.cfg.auth.mcp_origins()));
+    healthz.merge(mcp).with_state(shared)
+}
+
+fn cors_layer(policy: OriginPolicy) -> tower_http::cors::CorsLayer {
+    use axum::http::{HeaderName, Method};
+    use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+    let allow = AllowOrigin::predicate(move |origin, _| {
+        origin
+            .to_str()
+            .map(|o| security::origin_allowed(o, &policy.allowed_origins))
+            .unwrap_or(false)
+    });
+    CorsLayer::new()
+        .allow_origin(allow)
+        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_headers(Any)
+        .expose_headers([
+            HeaderName::from_static("mcp-session-id"),
+            HeaderName::from_static("www-authenticate"),
+        ])
 }
 
-async fn health(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
-    security::check_origin(&headers, &state.cfg.auth.allowed_origins)
-        .map(|_| (StatusCode::OK, Json(json!({"status":"ok"}))).into_response())
+async fn health(
+    State(state): State<AppState>,
+    connect: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let client_ip = client_ip(&state, connect.as_ref(), &headers);
+    security::check_cidrs(client_ip, &state.cfg.auth.allowed_cidrs)
+        .and_then(|_| security::check_origin_policy(&headers, &state.cfg.auth.healthz_origins()))
+        .map(|_| {
+            let exec = state.registry.exec_queue().stats();
+            (StatusCode::OK, Json(json!({"status":"ok","exec": exec}))).into_response()
+        })
         .unwrap_or_else(|e| into_response(e).into_response())
 }
 
@@ -118,15 +210,31 @@ async fn mcp_root_handler() -> impl IntoResponse {
 }
 
 async fn mcp_get_handler(
-    Path(path_token): Path<String>,
+    path_token: Option<Path<String>>,
     State(state): State<AppState>,
+    client_cert: Option<Extension<ClientCertificate>>,
+    connect: Option<ConnectInfo<SocketAddr>>,
+    matched: Option<MatchedPath>,
     headers: HeaderMap,
 ) -> Response {
+    let path_token = path_token.map(|Path(t)| t);
+    if path_token.is_none() && state.cfg.auth.mode == AuthMode::Token {
+        return mcp_root_handler().await.into_response();
+    }
     // For GET requests, be more lenient with Origin checking for direct browser access
-    if path_token != state.cfg.auth.bearer_token {
-        return into_response(AppError::Unauthorized).into_response();
+    let client_ip = client_ip(&state, connect.as_ref(), &headers);
+    let peer = connect.map(|ConnectInfo(a)| a.ip());
+    let cert = client_cert.map(|Extension(c)| c);
+    let attempt = identify(&state, &headers, &[], path_token.as_deref(), peer, client_ip, cert);
+    match guarded(&state, &headers, peer, client_ip, matched.as_ref(), attempt).await {
+        Ok(p) => {
+            if let Err(e) = state.rls.check(&p.name, client_ip) {
+                return into_response(e).into_response();
+            }
+        }
+        Err(e) => return into_response(e).into_response(),
     }
-    
+
     // Only check Origin if it's present (browsers don't send Origin for direct navigation)
     if headers.get("origin").is_some() {
         if let Err(e) = security::check_origin(&headers, &state.cfg.auth.allowed_origins) {
@@ -140,15 +248,18 @@ async fn mcp_get_handler(
     if accept_header.contains("text/event-stream") {
         // Return SSE connection for MCP clients
         let host = headers.get("host").and_then(|v| v.to_str().ok()).unwrap_or("localhost");
-        let sse_data = format!("data: {{\"jsonrpc\":\"2.0\",\"method\":\"connected\",\"params\":{{\"endpoint\":\"{}/mcp/{}\"}}}}\n\n", host, path_token);
+        let base = &state.cfg.server.base_path;
+        let endpoint = match &path_token {
+            Some(t) => format!("{base}/{t}"),
+            None => base.clone(),
+        };
+        let sse_data = format!("data: {{\"jsonrpc\":\"2.0\",\"method\":\"connected\",\"params\":{{\"endpoint\":\"{}{}\"}}}}\n\n", host, endpoint);
         
         let response = Response::builder()
             .status(StatusCode::OK)
             .header("content-type", "text/event-stream")
             .header("cache-control", "no-cache")
             .header("connection", "keep-alive")
-            .header("access-control-allow-origin", "*")
-            .header("access-control-expose-headers", "mcp-session-id,www-authenticate")
             .body(axum::body::Body::from(sse_data))
             .unwrap();
         response.into_response()
@@ -173,12 +284,58 @@ async fn mcp_get_handler(
 }
 
 async fn mcp_handler(
-    Path(path_token): Path<String>,
+    path_token: Option<Path<String>>,
     State(state): State<AppState>,
+    client_cert: Option<Extension<ClientCertificate>>,
+    connect: Option<ConnectInfo<SocketAddr>>,
+    matched: Option<MatchedPath>,
     headers: HeaderMap,
-    Json(req): Json<JsonRpcRequest>,
+    body: axum::body::Bytes,
 ) -> Response {
-    if let Err(e) = authorize_path(&state, &headers, &path_token) {
+    let started = std::time::Instant::now();
+    let req = match parse_rpc(&headers, &body) {
+        Ok(r) => r,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let path_token = path_token.map(|Path(t)| t);
+    let client_ip = client_ip(&state, connect.as_ref(), &headers);
+    let peer = connect.map(|ConnectInfo(a)| a.ip());
+    let cert = client_cert.map(|Extension(c)| c);
+    let attempt = authenticate(&state, &headers, &body, path_token.as_deref(), peer, client_ip, cert);
+    let principal = match guarded(&state, &headers, peer, client_ip, matched.as_ref(), attempt).await {
+        Ok(p) => p,
+        Err(e) => {
+            let error_resp = JsonRpcResponse {
+                jsonrpc: "2.0",
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32600,
+                    message: e.to_string(),
+                }),
+                id: req.id,
+            };
+            return (e.status(), e.retry_after(), Json(error_resp)).into_response();
+        }
+    };
+
+    if let Err(e) = state.rls.check(&principal.name, client_ip) {
+        let tool = req.params.get("name").and_then(|v| v.as_str()).unwrap_or("");
+        audit_end(
+            &uuid::Uuid::new_v4().to_string(),
+            headers.get("Origin").and_then(|v| v.to_str().ok()).unwrap_or(""),
+            bearer_present(&headers),
+            &Caller {
+                principal: principal.clone(),
+                client_ip,
+            },
+            tool,
+            "deny",
+            e.code(),
+            started.elapsed().as_millis() as u64,
+            0,
+            None,
+            e.denied_by(),
+        );
         let error_resp = JsonRpcResponse {
             jsonrpc: "2.0",
             result: None,
@@ -188,7 +345,7 @@ async fn mcp_handler(
             }),
             id: req.id,
         };
-        return (e.status(), Json(error_resp)).into_response();
+        return (e.status(), e.retry_after(), Json(error_resp)).into_response();
     }
 
     if req.jsonrpc != "2.0" {
@@ -207,8 +364,14 @@ async fn mcp_handler(
     match req.method.as_str() {
         "initialize" => handle_initialize(req).await,
         "initialized" => handle_initialized(req).await,
-        "tools/list" => handle_tools_list(state, req).await,
-        "tools/call" => handle_tools_call(state, headers, req).await,
+        "tools/list" => handle_tools_list(state, &principal, req).await,
+        "tools/call" => {
+            let caller = Caller {
+                principal,
+                client_ip,
+            };
+            handle_tools_call(state, headers, &caller, req).await
+        }
         _ => {
             let error_resp = JsonRpcResponse {
                 jsonrpc: "2.0",
@@ -254,11 +417,12 @@ async fn handle_initialized(req: JsonRpcRequest) -> Response {
     (StatusCode::OK, Json(resp)).into_response()
 }
 
-async fn handle_tools_list(state: AppState, req: JsonRpcRequest) -> Response {
+async fn handle_tools_list(state: AppState, principal: &Principal, req: JsonRpcRequest) -> Response {
     let tools: Vec<serde_json::Value> = state
         .registry
         .list_names()
         .into_iter()
+        .filter(|name| principal.allows_tool(name))
         .map(|name| {
             let tool = state.registry.get(&name).unwrap();
             let caps = tool.capabilities();
@@ -279,7 +443,12 @@ async fn handle_tools_list(state: AppState, req: JsonRpcRequest) -> Response {
     (StatusCode::OK, Json(resp)).into_response()
 }
 
-async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequest) -> Response {
+async fn handle_tools_call(
+    state: AppState,
+    headers: HeaderMap,
+    caller: &Caller,
+    req: JsonRpcRequest,
+) -> Response {
     let params = req.params.clone();
     let tool_name = params.get("name").and_then(|v| v.as_str()).unwrap_or("");
     let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
@@ -292,23 +461,21 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
         .and_then(|v| v.to_str().ok())
         .unwrap_or("")
         .to_string();
-    let token_present = headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok())
-        .map(|v| v.starts_with("Bearer "))
-        .unwrap_or(false);
+    let token_present = bearer_present(&headers);
 
     if let Err(e) = security::content_length_ok(&headers, state.cfg.limits.max_request_kb) {
         audit_end(
             &request_id,
             &origin,
             token_present,
+            caller,
             tool_name,
             "deny",
             e.code(),
             started.elapsed().as_millis() as u64,
             0,
             None,
+            e.denied_by(),
         );
         let error_resp = JsonRpcResponse {
             jsonrpc: "2.0",
@@ -322,18 +489,46 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
         return (e.status(), Json(error_resp)).into_response();
     }
 
-    let token = security::extract_bearer(&headers);
-    if let Err(e) = state.rls.check(token.as_deref()) {
+    if let Err(e) = state.rls.check_tool(tool_name, &caller.principal.name) {
+        audit_end(
+            &request_id,
+            &origin,
+            token_present,
+            caller,
+            tool_name,
+            "deny",
+            e.code(),
+            started.elapsed().as_millis() as u64,
+            0,
+            None,
+            e.denied_by(),
+        );
+        let error_resp = JsonRpcResponse {
+            jsonrpc: "2.0",
+            result: None,
+            error: Some(JsonRpcError {
+                code: -32600,
+                message: e.to_string(),
+            }),
+            id: req.id,
+        };
+        return (e.status(), e.retry_after(), Json(error_resp)).into_response();
+    }
+
+    if !caller.principal.allows_tool(tool_name) {
+        let e = AppError::ScopeDenied;
         audit_end(
             &request_id,
             &origin,
             token_present,
+            caller,
             tool_name,
             "deny",
             e.code(),
             started.elapsed().as_millis() as u64,
             0,
             None,
+            e.denied_by(),
         );
         let error_resp = JsonRpcResponse {
             jsonrpc: "2.0",
@@ -352,12 +547,14 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
             &request_id,
             &origin,
             token_present,
+            caller,
             tool_name,
             "deny",
             AppError::NotFound.code(),
             started.elapsed().as_millis() as u64,
             0,
             None,
+            None,
         );
         let error_resp = JsonRpcResponse {
             jsonrpc: "2.0",
@@ -371,23 +568,65 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
         return (StatusCode::NOT_FOUND, Json(error_resp)).into_response();
     };
 
+    // budget_status stays callable once a budget is exhausted
+    let budgets = state.registry.budgets();
+    if let Some(b) = budgets.as_ref().filter(|_| tool_name != "budget_status") {
+        if let Err(e) = b.check_and_count(&caller.principal.name) {
+            audit_end(
+                &request_id,
+                &origin,
+                token_present,
+                caller,
+                tool_name,
+                "deny",
+                e.code(),
+                started.elapsed().as_millis() as u64,
+                0,
+                None,
+                e.denied_by(),
+            );
+            let error_resp = JsonRpcResponse {
+                jsonrpc: "2.0",
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32600,
+                    message: e.to_string(),
+                }),
+                id: req.id,
+            };
+            return (e.status(), Json(error_resp)).into_response();
+        }
+    }
+
     let is_streaming = params
         .get("stream")
         .and_then(|v| v.as_bool())
         .unwrap_or(false);
+    let ctx = CallContext::new(caller.principal.clone()).with_budgets(budgets);
+    let approval = state
+        .approvals
+        .as_ref()
+        .filter(|q| q.requires_approval(tool_name, &arguments))
+        .map(|q| q.submit(&caller.principal.name, tool_name, &arguments));
     if is_streaming {
-        match tool.call_stream(arguments).await {
+        let res = match approval {
+            Some(pending) => Ok(approval_stream(pending, tool, ctx, arguments)),
+            None => tool.call_stream(&ctx, arguments).await,
+        };
+        match res {
             Ok(body) => {
                 audit_end(
                     &request_id,
                     &origin,
                     token_present,
+                    caller,
                     tool_name,
                     "allow",
                     "OK",
                     started.elapsed().as_millis() as u64,
                     0,
                     Some(true),
+                    None,
                 );
                 (
                     StatusCode::OK,
@@ -401,18 +640,48 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
                     &request_id,
                     &origin,
                     token_present,
+                    caller,
                     tool_name,
                     "error",
                     e.code(),
                     started.elapsed().as_millis() as u64,
                     0,
                     Some(true),
+                    e.denied_by(),
                 );
                 into_response(e).into_response()
             }
         }
     } else {
-        match tool.call(arguments).await {
+        if let Some(Err(e)) = match approval {
+            Some(pending) => Some(pending.wait().await),
+            None => None,
+        } {
+            audit_end(
+                &request_id,
+                &origin,
+                token_present,
+                caller,
+                tool_name,
+                "deny",
+                e.code(),
+                started.elapsed().as_millis() as u64,
+                0,
+                Some(false),
+                e.denied_by(),
+            );
+            let error_resp = JsonRpcResponse {
+                jsonrpc: "2.0",
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32600,
+                    message: e.to_string(),
+                }),
+                id: req.id,
+            };
+            return (e.status(), Json(error_resp)).into_response();
+        }
+        match tool.call(&ctx, arguments).await {
             Ok(result) => {
                 let resp = JsonRpcResponse {
                     jsonrpc: "2.0",
@@ -440,6 +709,7 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
                         &request_id,
                         &origin,
                         token_present,
+                        caller,
                         "allow",
                         "OK",
                         started.elapsed().as_millis() as u64,
@@ -448,18 +718,21 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
                         exit_code,
                         truncated,
                         timed_out,
+                        None,
                     );
                 } else {
                     audit_end(
                         &request_id,
                         &origin,
                         token_present,
+                        caller,
                         tool_name,
                         "allow",
                         "OK",
                         started.elapsed().as_millis() as u64,
                         bytes_out,
                         Some(false),
+                        None,
                     );
                 }
                 (StatusCode::OK, Json(resp)).into_response()
@@ -482,6 +755,7 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
                         &request_id,
                         &origin,
                         token_present,
+                        caller,
                         "error",
                         e.code(),
                         started.elapsed().as_millis() as u64,
@@ -490,18 +764,21 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
                         None,
                         None,
                         None,
+                        e.denied_by(),
                     );
                 } else {
                     audit_end(
                         &request_id,
                         &origin,
                         token_present,
+                        caller,
                         tool_name,
                         "error",
                         e.code(),
                         started.elapsed().as_millis() as u64,
                         bytes_out,
                         Some(false),
+                        e.denied_by(),
                     );
                 }
                 (e.status(), Json(error_resp)).into_response()
@@ -510,28 +787,69 @@ async fn handle_tools_call(state: AppState, headers: HeaderMap, req: JsonRpcRequ
     }
 }
 
+/// Emits `pending` while an operator decides, then the tool's own stream.
+fn approval_stream(
+    pending: PendingApproval,
+    tool: DynTool,
+    ctx: CallContext,
+    arguments: serde_json::Value,
+) -> StreamBody {
+    use futures::StreamExt;
+    let line = |v: serde_json::Value| Ok::<_, std::io::Error>(bytes::Bytes::from(format!("{v}\n")));
+    let stream = async_stream::stream! {
+        yield line(json!({"event":"pending","approval_id": pending.id()}));
+        let res = match pending.wait().await {
+            Ok(()) => tool.call_stream(&ctx, arguments).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(body) => {
+                let mut data = body.into_data_stream();
+                while let Some(chunk) = data.next().await {
+                    yield chunk.map_err(std::io::Error::other);
+                }
+            }
+            Err(e) => yield line(json!({"event":"error","error":{"code": e.code(),"message": e.to_string()}})),
+        }
+    };
+    StreamBody::from_stream(stream)
+}
+
+/// Whether the request carried a bearer token, for audit lines.
+fn bearer_present(headers: &HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("Bearer "))
+}
+
 #[allow(clippy::too_many_arguments)]
 fn audit_end(
     request_id: &str,
     origin: &str,
     token_present: bool,
+    caller: &Caller,
     tool: &str,
     decision: &str,
     code: &str,
     duration_ms: u64,
     bytes_out: u64,
     streaming: Option<bool>,
+    denied_by: Option<&str>,
 ) {
     tracing::info!(
         request_id = request_id,
         origin = origin,
         token_present = token_present,
+        principal = caller.principal.name.as_str(),
+        client_ip = caller.client_ip.map(|ip| ip.to_string()).as_deref(),
         tool = tool,
         decision = decision,
         code = code,
         duration_ms = duration_ms,
         bytes_out = bytes_out,
         streaming = ?streaming,
+        denied_by = denied_by,
         "audit"
     );
 }
@@ -541,6 +859,7 @@ fn audit_end_exec(
     request_id: &str,
     origin: &str,
     token_present: bool,
+    caller: &Caller,
     decision: &str,
     code: &str,
     duration_ms: u64,
@@ -549,11 +868,14 @@ fn audit_end_exec(
     exit_code: Option<i64>,
     truncated: Option<bool>,
     timed_out: Option<bool>,
+    denied_by: Option<&str>,
 ) {
     tracing::info!(
         request_id = request_id,
         origin = origin,
         token_present = token_present,
+        principal = caller.principal.name.as_str(),
+        client_ip = caller.client_ip.map(|ip| ip.to_string()).as_deref(),
         tool = "exec",
         decision = decision,
         code = code,
@@ -563,14 +885,147 @@ fn audit_end_exec(
         exit_code = exit_code,
         truncated = truncated,
         timed_out = timed_out,
+        denied_by = denied_by,
         "audit"
     );
 }
 
-fn authorize_path(state: &AppState, headers: &HeaderMap, path_token: &str) -> Result<(), AppError> {
-    if path_token != state.cfg.auth.bearer_token {
-        return Err(AppError::Unauthorized);
+/// Runs an authentication attempt under the brute-force lockout and audits failures.
+/// Failures are counted per route that was requested (`/mcp` or `/mcp/:token`),
+/// not per full path, so each token guess lands on the same counter.
+async fn guarded(
+    state: &AppState,
+    headers: &HeaderMap,
+    peer: Option<IpAddr>,
+    client_ip: Option<IpAddr>,
+    matched: Option<&MatchedPath>,
+    attempt: impl std::future::Future<Output = Result<Principal, AppError>>,
+) -> Result<Principal, AppError> {
+    let prefix = matched.map_or(state.cfg.server.base_path.as_str(), |m| m.as_str());
+    let now = std::time::Instant::now();
+    let trusted = &state.cfg.auth.trusted_proxies;
+    let forwarded = peer.is_some_and(|p| security::is_forwarded(p, headers, trusted));
+    let res = match state.lockout.check(client_ip, forwarded, prefix, now) {
+        Ok(()) => attempt.await,
+        Err(e) => Err(e),
+    };
+    match &res {
+        Ok(_) => state.lockout.record_success(client_ip, forwarded, prefix),
+        Err(e) => {
+            let banned_for = match e {
+                AppError::Banned { .. } => None,
+                _ => state.lockout.record_failure(client_ip, forwarded, prefix, now),
+            };
+            let reason = match e {
+                AppError::Unauthorized => match state.cfg.auth.mode {
+                    AuthMode::Token => "bad token",
+                    AuthMode::Tailscale => "unknown tailnet identity",
+                    AuthMode::Hmac => "bad signature",
+                },
+                AppError::OriginDenied => "bad origin",
+                AppError::RequestExpired => "expired",
+                AppError::NonceReused => "replayed nonce",
+                AppError::IpDenied => "address not allowed",
+                AppError::Banned { .. } => "banned",
+                _ => "other",
+            };
+            tracing::info!(
+                origin = headers.get("Origin").and_then(|v| v.to_str().ok()).unwrap_or(""),
+                client_ip = client_ip.map(|ip| ip.to_string()).as_deref(),
+                prefix = prefix,
+                decision = "deny",
+                code = e.code(),
+                reason = reason,
+                banned_s = banned_for.map(|d| d.as_secs()),
+                "audit"
+            );
+        }
+    }
+    res
+}
+
+fn client_ip(
+    state: &AppState,
+    connect: Option<&ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+) -> Option<IpAddr> {
+    connect.map(|ConnectInfo(peer)| {
+        security::resolve_client_ip(peer.ip(), headers, &state.cfg.auth.trusted_proxies)
+    })
+}
+
+/// Same checks as the `Json` extractor; the raw body is kept for signature checks.
+fn parse_rpc(headers: &HeaderMap, body: &[u8]) -> Result<JsonRpcRequest, (StatusCode, String)> {
+    let is_json = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json") || ct.contains("+json"));
+    if !is_json {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected request with `Content-Type: application/json`".to_string(),
+        ));
     }
-    security::check_origin(headers, &state.cfg.auth.allowed_origins)?;
-    Ok(())
+    Json::<JsonRpcRequest>::from_bytes(body)
+        .map(|Json(r)| r)
+        .map_err(|e| (e.status(), e.body_text()))
+}
+
+/// Resolves the caller's principal for the configured auth mode and applies the
+/// client address allowlist. Origin is checked separately by the caller.
+async fn identify(
+    state: &AppState,
+    headers: &HeaderMap,
+    body: &[u8],
+    path_token: Option<&str>,
+    peer: Option<IpAddr>,
+    client_ip: Option<IpAddr>,
+    client_cert: Option<ClientCertificate>,
+) -> Result<Principal, AppError> {
+    let principal = match state.cfg.auth.mode {
+        AuthMode::Token => {
+            if path_token != Some(state.cfg.auth.bearer_token.as_str()) {
+                return Err(AppError::Unauthorized);
+            }
+            let name = client_cert.map(|c| c.principal);
+            Principal::unrestricted(name.unwrap_or_else(|| "token".to_string()))
+        }
+        AuthMode::Tailscale => {
+            let ts = state
+                .cfg
+                .auth
+                .tailscale
+                .as_ref()
+                .ok_or(AppError::Unauthorized)?;
+            crate::tailscale::authenticate(ts, peer, client_ip, headers)
+                .await
+                .ok_or(AppError::Unauthorized)?
+        }
+        AuthMode::Hmac => {
+            let verifier = state.verifier.as_ref().ok_or(AppError::Unauthorized)?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            verifier.verify(headers, body, now)?;
+            let name = client_cert.map(|c| c.principal);
+            Principal::unrestricted(name.unwrap_or_else(|| verifier.principal.clone()))
+        }
+    };
+    security::check_cidrs(client_ip, &state.cfg.auth.allowed_cidrs)?;
+    Ok(principal)
+}
+
+async fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+    body: &[u8],
+    path_token: Option<&str>,
+    peer: Option<IpAddr>,
+    client_ip: Option<IpAddr>,
+    client_cert: Option<ClientCertificate>,
+) -> Result<Principal, AppError> {
+    let principal = identify(state, headers, body, path_token, peer, client_ip, client_cert).await?;
+    security::check_origin_policy(headers, &state.cfg.auth.mcp_origins())?;
+    Ok(principal)
 }
@@ -0,0 +1,128 @@
+use crate::{
+    config::{Tailscale, TailnetPrincipal},
+    security::Principal,
+};
+use axum::http::HeaderMap;
+use http_body_util::{BodyExt, Empty};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::UnixStream;
+
+/// Longest a whois may take; a wedged tailscaled must not hang requests.
+const WHOIS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Tailnet identity of a caller, from `tailscale serve` headers or a LocalAPI whois.
+#[derive(Debug, Clone, Default)]
+pub struct TailnetIdentity {
+    pub login: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Identity headers added by `tailscale serve` for user-owned nodes. Only meaningful
+/// when the request came from the local tailscaled proxy.
+pub fn identity_from_headers(headers: &HeaderMap) -> Option<TailnetIdentity> {
+    let login = headers
+        .get("tailscale-user-login")
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty())?;
+    Some(TailnetIdentity {
+        login: Some(login.to_string()),
+        tags: vec![],
+    })
+}
+
+#[derive(Deserialize)]
+struct WhoisResponse {
+    #[serde(rename = "Node")]
+    node: Option<WhoisNode>,
+    #[serde(rename = "UserProfile")]
+    user: Option<WhoisUser>,
+}
+
+#[derive(Deserialize)]
+struct WhoisNode {
+    #[serde(rename = "Tags", default)]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct WhoisUser {
+    #[serde(rename = "LoginName", default)]
+    login_name: String,
+}
+
+/// Asks tailscaled which node and user own `ip`.
+pub async fn whois(socket: &Path, ip: IpAddr) -> anyhow::Result<TailnetIdentity> {
+    tokio::time::timeout(WHOIS_TIMEOUT, whois_inner(socket, ip))
+        .await
+        .map_err(|_| anyhow::anyhow!("whois timed out"))?
+}
+
+async fn whois_inner(socket: &Path, ip: IpAddr) -> anyhow::Result<TailnetIdentity> {
+    let stream = UnixStream::connect(socket).await?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+    let req = http::Request::get(format!("/localapi/v0/whois?addr={ip}"))
+        .header("host", "local-tailscaled.sock")
+        .body(Empty::<bytes::Bytes>::new())?;
+    let resp = sender.send_request(req).await?;
+    if !resp.status().is_success() {
+        anyhow::bail!("whois failed: {}", resp.status());
+    }
+    let body = resp.into_body().collect().await?.to_bytes();
+    let parsed: WhoisResponse = serde_json::from_slice(&body)?;
+    let tags = parsed.node.and_then(|n| n.tags).unwrap_or_default();
+    // tagged nodes report a placeholder user; the tags are their identity
+    let login = parsed
+        .user
+        .map(|u| u.login_name)
+        .filter(|l| !l.is_empty() && tags.is_empty());
+    Ok(TailnetIdentity { login, tags })
+}
+
+pub fn map_principal(rules: &[TailnetPrincipal], ident: &TailnetIdentity) -> Option<Principal> {
+    rules.iter().find_map(|r| {
+        let matched = if r.matches == "*" {
+            ident.login.clone().or_else(|| ident.tags.first().cloned())
+        } else if r.matches.starts_with("tag:") {
+            ident.tags.iter().find(|t| **t == r.matches).cloned()
+        } else {
+            ident.login.clone().filter(|l| *l == r.matches)
+        }?;
+        Some(Principal {
+            name: r.principal.clone().unwrap_or(matched),
+            scopes: r.scopes.clone(),
+        })
+    })
+}
+
+/// Resolves a tailnet principal. `peer` must be loopback (the tailscaled proxy);
+/// `client_ip` is the tailnet address used for whois when no identity headers are present.
+pub async fn authenticate(
+    cfg: &Tailscale,
+    peer: Option<IpAddr>,
+    client_ip: Option<IpAddr>,
+    headers: &HeaderMap,
+) -> Option<Principal> {
+    if !peer.is_some_and(|p| p.is_loopback()) {
+        return None;
+    }
+    let ident = match identity_from_headers(headers) {
+        Some(i) => i,
+        None if cfg.use_whois => {
+            let ip = client_ip.filter(|ip| !ip.is_loopback())?;
+            match whois(&cfg.socket, ip).await {
+                Ok(i) => i,
+                Err(e) => {
+                    tracing::warn!(error = %e, ip = %ip, "tailscale whois failed");
+                    return None;
+                }
+            }
+        }
+        None => return None,
+    };
+    map_principal(&cfg.principals, &ident)
+}
@@ -0,0 +1,189 @@
+use crate::config::Tls;
+use axum::{extract::ConnectInfo, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+/// Verified client certificate attached to every request on an mTLS connection.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub principal: String,
+}
+
+/// Holds the active rustls config and swaps it when cert/key/CA files change on disk.
+pub struct TlsReloader {
+    cfg: Tls,
+    current: RwLock<Arc<ServerConfig>>,
+    stamps: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl TlsReloader {
+    pub fn new(cfg: &Tls) -> anyhow::Result<Self> {
+        let server_cfg = load_server_config(cfg)?;
+        Ok(Self {
+            cfg: cfg.clone(),
+            current: RwLock::new(Arc::new(server_cfg)),
+            stamps: Mutex::new(file_stamps(cfg)),
+        })
+    }
+
+    pub fn current(&self) -> Arc<ServerConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reloads if any watched file changed. Returns true when a new config was installed;
+    /// a broken file keeps the previous config in place.
+    pub fn reload_if_changed(&self) -> bool {
+        let stamps = file_stamps(&self.cfg);
+        let mut seen = self.stamps.lock().unwrap();
+        if *seen == stamps {
+            return false;
+        }
+        *seen = stamps;
+        match load_server_config(&self.cfg) {
+            Ok(c) => {
+                *self.current.write().unwrap() = Arc::new(c);
+                tracing::info!(cert = %self.cfg.cert.display(), "tls config reloaded");
+                true
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "tls reload failed; keeping previous config");
+                false
+            }
+        }
+    }
+
+    pub fn spawn_watcher(self: &Arc<Self>) {
+        let this = self.clone();
+        let every = Duration::from_secs(self.cfg.reload_interval_s);
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(every);
+            tick.tick().await;
+            loop {
+                tick.tick().await;
+                this.reload_if_changed();
+            }
+        });
+    }
+
+    pub fn identify(&self, cert: &CertificateDer<'_>) -> Option<ClientCertificate> {
+        let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+        let subject = parsed.subject().to_string();
+        let cn = parsed
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|a| a.as_str().ok())
+            .unwrap_or(&subject)
+            .to_string();
+        let principal = self.cfg.client_principals.get(&cn).cloned().unwrap_or(cn);
+        Some(ClientCertificate { principal })
+    }
+}
+
+fn watched_files(cfg: &Tls) -> Vec<PathBuf> {
+    let mut files = vec![cfg.cert.clone(), cfg.key.clone()];
+    files.extend(cfg.client_ca.clone());
+    files
+}
+
+fn file_stamps(cfg: &Tls) -> Vec<Option<SystemTime>> {
+    watched_files(cfg)
+        .iter()
+        .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+pub fn load_server_config(cfg: &Tls) -> anyhow::Result<ServerConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let certs = CertificateDer::pem_file_iter(&cfg.cert)?.collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificates in {}", cfg.cert.display());
+    }
+    let key = PrivateKeyDer::from_pem_file(&cfg.key)?;
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &cfg.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for c in CertificateDer::pem_file_iter(ca)? {
+                roots.add(c?)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if cfg.require_client_cert {
+                verifier.build()?
+            } else {
+                verifier.allow_unauthenticated().build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server = builder.with_single_cert(certs, key)?;
+    server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server)
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: Arc<TlsReloader>,
+) -> anyhow::Result<()> {
+    tls.spawn_watcher();
+    loop {
+        let (tcp, peer) = match listener.accept().await {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!(error = %e, "accept failed");
+                continue;
+            }
+        };
+        let acceptor = TlsAcceptor::from(tls.current());
+        let app = app.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(tcp).await {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::debug!(peer = %peer, error = %e, "tls handshake failed");
+                    return;
+                }
+            };
+            let client = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|c| c.first())
+                .and_then(|c| tls.identify(c));
+            let svc = app.map_request(move |mut req: http::Request<Incoming>| {
+                req.extensions_mut().insert(ConnectInfo(peer));
+                if let Some(c) = &client {
+                    req.extensions_mut().insert(c.clone());
+                }
+                req
+            });
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(svc))
+                .await
+            {
+                tracing::debug!(peer = %peer, error = %e, "connection error");
+            }
+        });
+    }
+}
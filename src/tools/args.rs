@@ -0,0 +1,120 @@
+use crate::{config::ArgPolicy, errors::AppError, tools::resolve::Resolver};
+use regex::Regex;
+use std::path::Path;
+
+/// A command's [`ArgPolicy`] with its patterns compiled. Denials name the
+/// rule that fired so the audit log can say why a call was refused.
+#[derive(Debug)]
+pub struct ArgRules {
+    cmd: String,
+    subcommands: Vec<String>,
+    deny_flags: Vec<String>,
+    positional: Vec<Regex>,
+    max_args: Option<usize>,
+    paths_in_root: bool,
+}
+
+impl ArgRules {
+    pub fn new(cmd: &str, policy: &ArgPolicy) -> anyhow::Result<Self> {
+        let positional = policy
+            .positional
+            .iter()
+            .map(|p| Regex::new(&format!("^(?:{p})$")))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            cmd: cmd.to_string(),
+            subcommands: policy.subcommands.clone(),
+            deny_flags: policy.deny_flags.clone(),
+            positional,
+            max_args: policy.max_args,
+            paths_in_root: policy.paths_in_root,
+        })
+    }
+
+    /// `cwd` is the absolute working directory relative arguments resolve from.
+    pub fn check(&self, args: &[String], cwd: &Path, resolver: &Resolver) -> Result<(), AppError> {
+        if self.max_args.is_some_and(|max| args.len() > max) {
+            return Err(self.deny("max_args"));
+        }
+        let mut flags_done = false;
+        let mut first = true;
+        for arg in args {
+            if !flags_done && arg == "--" {
+                flags_done = true;
+                continue;
+            }
+            let value = if !flags_done && arg.starts_with('-') {
+                if let Some(flag) = self.denied_flag(arg) {
+                    return Err(self.deny(&format!("deny_flags {flag}")));
+                }
+                // `--flag=value`, or a value attached to a short option
+                // (`-o/path`, `-xf/path`); a separate value is checked as a
+                // positional
+                let value = match split_short(arg) {
+                    Some((_, rest)) => rest.strip_prefix('=').unwrap_or(rest),
+                    None => arg.split_once('=').map_or("", |(_, v)| v),
+                };
+                if value.is_empty() {
+                    continue;
+                }
+                value
+            } else if std::mem::take(&mut first) && !self.subcommands.is_empty() {
+                if !self.subcommands.contains(arg) {
+                    return Err(self.deny(&format!("subcommands ({arg})")));
+                }
+                continue;
+            } else {
+                if !self.positional.is_empty() && !self.positional.iter().any(|re| re.is_match(arg))
+                {
+                    return Err(self.deny(&format!("positional ({arg})")));
+                }
+                arg
+            };
+            if self.paths_in_root && is_path_like(value) && !inside_root(value, cwd, resolver) {
+                return Err(self.deny(&format!("paths_in_root ({value})")));
+            }
+        }
+        Ok(())
+    }
+
+    fn denied_flag(&self, arg: &str) -> Option<&str> {
+        let name = arg.split_once('=').map_or(arg, |(n, _)| n);
+        let bundle = split_short(arg).map_or("", |(b, _)| b);
+        self.deny_flags
+            .iter()
+            .find(|f| {
+                if *f == name {
+                    return true;
+                }
+                // `-f` inside a bundle like `-xf` or `-xf/path`
+                let short = f.len() == 2 && f.starts_with('-') && *f != "--";
+                short && bundle.contains(&f[1..])
+            })
+            .map(String::as_str)
+    }
+
+    fn deny(&self, rule: &str) -> AppError {
+        AppError::ArgDenied(format!("[exec.commands.{}.args] {rule}", self.cmd))
+    }
+}
+
+/// Splits a short-option argument into its bundled letters and whatever
+/// follows them, which may be an attached value: `-xf/tmp/a` gives
+/// `("xf", "/tmp/a")`. Which letter takes the value isn't known, so the
+/// bundle runs up to the first character that can't be an option letter.
+fn split_short(arg: &str) -> Option<(&str, &str)> {
+    let body = arg.strip_prefix('-').filter(|b| !b.starts_with('-'))?;
+    let end = body
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(body.len());
+    Some(body.split_at(end))
+}
+
+fn is_path_like(arg: &str) -> bool {
+    arg.contains('/') || arg == "." || arg == ".." || arg.starts_with('~')
+}
+
+fn inside_root(arg: &str, cwd: &Path, resolver: &Resolver) -> bool {
+    // the shell isn't involved, but a tool might expand `~` itself
+    !arg.starts_with('~') && resolver.resolve(&cwd.join(arg)).is_ok()
+}
@@ -0,0 +1,28 @@
+use crate::{
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+};
+use async_trait::async_trait;
+use serde_json::json;
+
+/// Reports the caller's remaining daily and monthly budget.
+pub struct BudgetStatusTool;
+
+#[async_trait]
+impl Tool for BudgetStatusTool {
+    fn capabilities(&self) -> serde_json::Value {
+        json!({"input": {"type":"object","properties": {}}, "output": {"type":"object","properties": {"principal":{"type":"string"},"daily":{"type":"object"},"monthly":{"type":"object"}}}})
+    }
+
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        _params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let budgets = ctx
+            .budgets
+            .as_ref()
+            .ok_or_else(|| AppError::ToolError("budgets not configured".into()))?;
+        Ok(budgets.status(&ctx.principal.name))
+    }
+}
@@ -1,55 +1,176 @@
-use crate::{config::Config, errors::AppError, mcp::registry::Tool};
+use crate::{
+    budget::Usage,
+    config::{Access, Config, Permission, Rlimits},
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    redact::Redactor,
+    tools::{
+        args::ArgRules,
+        pin::Pins,
+        process::{ChildProcess, ExitInfo},
+        queue::{ExecPermit, ExecQueue},
+        resolve::Resolver,
+        sandbox::Sandbox,
+        stdin::{StdinSource, StdinStreams},
+        PathPolicy,
+    },
+};
 use async_trait::async_trait;
 use axum::body::Body;
 use base64::Engine;
 use serde_json::json;
-use std::{collections::HashSet, path::PathBuf, process::Stdio, time::Instant};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Instant,
+};
 use tokio::{
     io::AsyncReadExt,
-    process::Command,
+    task::JoinHandle,
     time::{timeout, Duration},
 };
 
 pub struct ExecTool {
     allowed: HashSet<PathBuf>,
+    /// Bare names from `allowed_cmds` and what PATH resolved them to at startup.
+    names: HashMap<String, PathBuf>,
+    pins: Arc<Pins>,
+    deny_root_binaries: bool,
     pass_env: Vec<String>,
     timeout_s: u64,
     max_stdout_kb: usize,
+    kill_grace: Duration,
+    queue: Arc<ExecQueue>,
+    redactor: Arc<Redactor>,
+    root: PathBuf,
+    policy: PathPolicy,
+    sandbox: Sandbox,
+    /// Per-command sandboxes from `[exec.commands]`, by resolved binary.
+    sandboxes: HashMap<PathBuf, Sandbox>,
+    arg_rules: HashMap<PathBuf, ArgRules>,
+    /// Default working directories from `[exec.commands]`, relative to root.
+    cwds: HashMap<PathBuf, PathBuf>,
+    resolver: Resolver,
+    max_stdin_bytes: usize,
+    stdin_streams: Arc<StdinStreams>,
+}
+
+/// A validated exec call: the resolved binary plus its arguments.
+struct ExecRequest {
+    full: PathBuf,
+    args: Vec<String>,
+    /// Absolute working directory under root.
+    cwd: PathBuf,
+    stdin: StdinSource,
+    timeout_s: u64,
+}
+
+/// How a child's stdio is set up and what ties it to Valet.
+#[derive(Clone, Copy, PartialEq)]
+enum Attach {
+    /// Piped stdout and stderr, in a process group of its own.
+    Pipes,
+    /// A terminal the caller attaches, as its controlling terminal.
+    Tty,
+    /// Like `Pipes`, and killed when Valet exits.
+    Service,
+}
+
+/// A spawned background child, see [`ExecTool::spawn_job`].
+pub struct JobChild {
+    pub child: ChildProcess,
+    pub feeder: JoinHandle<()>,
+    pub cwd: String,
+    pub rlimits: Rlimits,
+    /// A job's slot in the exec queue, held for as long as it runs.
+    pub permit: Option<ExecPermit>,
 }
 
 impl ExecTool {
     pub fn new(cfg: &Config) -> anyhow::Result<Self> {
         let resolved = resolve_cmds(&cfg.exec.allowed_cmds)?;
+        let root = dunce::canonicalize(&cfg.root.root_dir)?;
+        if cfg.exec.deny_root_binaries {
+            if let Some(full) = resolved.iter().find(|f| f.starts_with(&root)) {
+                anyhow::bail!("allowed command {} is inside root_dir", full.display());
+            }
+        }
+        let mut names = HashMap::new();
+        for name in cfg.exec.allowed_cmds.iter().filter(|c| !c.contains('/')) {
+            names.insert(name.clone(), resolve_cmd(name)?);
+        }
+        let mut sandboxes = HashMap::new();
+        let mut digests = HashMap::new();
+        let mut arg_rules = HashMap::new();
+        let mut cwds = HashMap::new();
+        for (name, policy) in &cfg.exec.commands {
+            let full = resolve_cmd(name)?;
+            if !resolved.contains(&full) {
+                anyhow::bail!("[exec.commands.{name}] is not in allowed_cmds");
+            }
+            if let Some(cwd) = &policy.cwd {
+                cwds.insert(full.clone(), cwd.clone());
+            }
+            if let Some(args) = &policy.args {
+                arg_rules.insert(full.clone(), ArgRules::new(name, args)?);
+            }
+            if let Some(sha) = &policy.sha256 {
+                digests.insert(full.clone(), hex::decode(sha)?);
+            }
+            sandboxes.insert(full, Sandbox::new(&cfg.exec, Some(policy), &root)?);
+        }
+        let pins = Pins::new(digests);
+        // fail at startup rather than on the first call
+        for full in &resolved {
+            pins.verify(full)
+                .map_err(|e| anyhow::anyhow!("{}: {e}", full.display()))?;
+        }
+        let limits = &cfg.limits;
         Ok(Self {
             allowed: resolved,
+            names,
+            pins: Arc::new(pins),
+            deny_root_binaries: cfg.exec.deny_root_binaries,
             pass_env: cfg.exec.pass_env.clone(),
-            timeout_s: cfg.limits.exec_timeout_s,
-            max_stdout_kb: cfg.limits.max_stdout_kb,
+            timeout_s: limits.exec_timeout_s,
+            max_stdout_kb: limits.max_stdout_kb,
+            kill_grace: Duration::from_millis(limits.exec_kill_grace_ms),
+            queue: Arc::new(ExecQueue::new(
+                limits.max_concurrent_exec,
+                limits.max_concurrent_exec_per_principal,
+                limits.exec_queue_max,
+                Duration::from_secs(limits.exec_queue_timeout_s),
+            )),
+            redactor: Arc::new(Redactor::new(cfg)?),
+            sandbox: Sandbox::new(&cfg.exec, None, &root)?,
+            sandboxes,
+            arg_rules,
+            cwds,
+            max_stdin_bytes: limits.max_stdin_kb * 1024,
+            stdin_streams: Arc::new(StdinStreams::new(limits.max_stdin_kb * 1024)),
+            resolver: Resolver::new(&root, cfg.paths.symlinks)?,
+            root,
+            policy: PathPolicy::new(cfg)?,
         })
     }
-}
 
-fn resolve_cmds(cmds: &[String]) -> anyhow::Result<HashSet<PathBuf>> {
-    let mut set = HashSet::new();
-    for c in cmds {
-        let path = if c.contains('/') {
-            PathBuf::from(c)
-        } else {
-            which::which(c)?
-        };
-        let can = dunce::canonicalize(path)?;
-        set.insert(can);
+    pub fn queue(&self) -> Arc<ExecQueue> {
+        self.queue.clone()
     }
-    Ok(set)
-}
 
-#[async_trait]
-impl Tool for ExecTool {
-    fn capabilities(&self) -> serde_json::Value {
-        json!({"input": {"type":"object","required":["cmd"],"properties": {"cmd": {"type":"string"},"args":{"type":"array","items":{"type":"string"}},"timeout_s":{"type":"integer"}}}, "output": {"type":"object","properties": {"exit_code":{"type":"integer"},"stdout_b64":{"type":"string"},"stderr_b64":{"type":"string"},"duration_ms":{"type":"integer"},"truncated":{"type":"boolean"},"timed_out":{"type":"boolean"}}}})
+    pub fn stdin_streams(&self) -> Arc<StdinStreams> {
+        self.stdin_streams.clone()
     }
 
-    async fn call(&self, params: serde_json::Value) -> Result<serde_json::Value, AppError> {
+    fn prepare(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<ExecRequest, AppError> {
         let cmd = params
             .get("cmd")
             .and_then(|v| v.as_str())
@@ -78,12 +199,240 @@ impl Tool for ExecTool {
         if !self.allowed.contains(&full) {
             return Err(AppError::ExecDenied);
         }
+        if self.names.get(cmd).is_some_and(|was| *was != full) {
+            tracing::warn!(cmd, path = %full.display(), "allowed command now resolves elsewhere");
+            return Err(AppError::ExecDenied);
+        }
+        if self.deny_root_binaries && full.starts_with(&self.root) {
+            return Err(AppError::ExecDenied);
+        }
+        let cwd = params
+            .get("cwd")
+            .and_then(|v| v.as_str())
+            .map(Path::new)
+            .or(self.cwds.get(&full).map(PathBuf::as_path))
+            .unwrap_or(Path::new("."));
+        let cwd = self.resolver.resolve(cwd)?;
+        if !cwd.is_dir() {
+            return Err(if cwd.exists() {
+                AppError::ToolError("cwd is not a directory".into())
+            } else {
+                AppError::NotFound
+            });
+        }
+        let cwd = cwd.path;
+        self.policy.check("exec", Access::Read, &cwd)?;
+        self.policy
+            .permit(&ctx.principal.name, Permission::ExecCwd, &cwd)?;
+        if let Some(rules) = self.arg_rules.get(&full) {
+            rules.check(&args, &cwd, &self.resolver)?;
+        }
+        let stdin = self.stdin(ctx, params)?;
+        Ok(ExecRequest {
+            full,
+            args,
+            cwd,
+            stdin,
+            timeout_s,
+        })
+    }
+
+    /// At most one of `stdin_b64`, `stdin_text`, `stdin_path` (relative to
+    /// root) and `stdin_stream`; none means an empty stdin.
+    fn stdin(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<StdinSource, AppError> {
+        let keys = ["stdin_b64", "stdin_text", "stdin_path", "stdin_stream"];
+        let given: Vec<_> = keys.iter().filter(|k| params.get(**k).is_some()).collect();
+        let too_big = || AppError::ToolError("stdin exceeds max_stdin_kb".into());
+        let str_param = |key: &str| {
+            params[key]
+                .as_str()
+                .ok_or_else(|| AppError::ToolError(format!("{key} must be a string")))
+        };
+        let source = match given[..] {
+            [] => StdinSource::Null,
+            [&"stdin_b64"] => StdinSource::Bytes(
+                base64::engine::general_purpose::STANDARD
+                    .decode(str_param("stdin_b64")?)
+                    .map_err(|_| AppError::ToolError("invalid base64 in stdin_b64".into()))?,
+            ),
+            [&"stdin_text"] => StdinSource::Bytes(str_param("stdin_text")?.as_bytes().to_vec()),
+            [&"stdin_path"] => {
+                let target = self.resolver.resolve(Path::new(str_param("stdin_path")?))?;
+                self.policy.check("exec", Access::Read, &target.path)?;
+                self.policy
+                    .permit(&ctx.principal.name, Permission::Read, &target.path)?;
+                if target.stat()?.size > self.max_stdin_bytes as u64 {
+                    return Err(too_big());
+                }
+                StdinSource::File(target.open_read()?)
+            }
+            [&"stdin_stream"] if params["stdin_stream"].as_bool() == Some(true) => {
+                StdinSource::Stream
+            }
+            [&"stdin_stream"] => StdinSource::Null,
+            _ => {
+                return Err(AppError::ToolError(
+                    "pass only one of stdin_b64, stdin_text, stdin_path, stdin_stream".into(),
+                ))
+            }
+        };
+        if matches!(&source, StdinSource::Bytes(b) if b.len() > self.max_stdin_bytes) {
+            return Err(too_big());
+        }
+        Ok(source)
+    }
 
-        let mut command = Command::new(&full);
-        command.args(&args);
-        command.stdin(Stdio::null());
-        command.stdout(Stdio::piped());
-        command.stderr(Stdio::piped());
+    /// `path` relative to root, `.` for root itself.
+    fn relative(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.root) {
+            Ok(rel) if !rel.as_os_str().is_empty() => rel.display().to_string(),
+            _ => ".".to_string(),
+        }
+    }
+
+    fn sandbox(&self, full: &Path) -> &Sandbox {
+        self.sandboxes.get(full).unwrap_or(&self.sandbox)
+    }
+
+    /// Builds the command for a PTY session under the same policy as `exec`.
+    /// The caller attaches the terminal to stdio; the child starts a session
+    /// with it as the controlling terminal.
+    pub fn pty_command(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<(Command, String), AppError> {
+        let req = self.prepare(ctx, params)?;
+        if !matches!(req.stdin, StdinSource::Null) {
+            return Err(AppError::ToolError(
+                "PTY sessions take input via pty_write".into(),
+            ));
+        }
+        let mut command = self.command(&req, Attach::Tty)?;
+        self.pins.pin(&mut command, &req.full)?;
+        Ok((command, self.relative(&req.cwd)))
+    }
+
+    /// Starts a background job under the same policy as `exec`, once the
+    /// exec queue has a slot for it. The caller owns the child and reads its
+    /// piped stdout and stderr; stdin is fed from the inline or `stdin_path`
+    /// input.
+    pub async fn spawn_job(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<JobChild, AppError> {
+        let req = self.prepare_background(ctx, params)?;
+        let permit = self
+            .queue
+            .enqueue(&ctx.principal.name)?
+            .wait(|_| {})
+            .await?;
+        let mut job = self.spawn_background(req, &HashMap::new(), Attach::Pipes)?;
+        job.permit = Some(permit);
+        Ok(job)
+    }
+
+    /// Starts a configured service under the same policy as `exec`, with
+    /// `env` on top of `pass_env`. Like a job, but it dies with Valet.
+    pub fn spawn_service(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+        env: &HashMap<String, String>,
+    ) -> Result<JobChild, AppError> {
+        let req = self.prepare_background(ctx, params)?;
+        self.spawn_background(req, env, Attach::Service)
+    }
+
+    fn prepare_background(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<ExecRequest, AppError> {
+        let req = self.prepare(ctx, params)?;
+        if matches!(req.stdin, StdinSource::Stream) {
+            return Err(AppError::ToolError(
+                "jobs take stdin_text, stdin_b64 or stdin_path".into(),
+            ));
+        }
+        Ok(req)
+    }
+
+    fn spawn_background(
+        &self,
+        req: ExecRequest,
+        env: &HashMap<String, String>,
+        attach: Attach,
+    ) -> Result<JobChild, AppError> {
+        let mut command = self.command(&req, attach)?;
+        command.envs(env);
+        self.pins.pin(&mut command, &req.full)?;
+        let mut child = ChildProcess::spawn(&mut command)
+            .map_err(|e| AppError::Internal(format!("failed to spawn: {e}")))?;
+        let feeder = req
+            .stdin
+            .feed(child.stdin.take(), None, self.max_stdin_bytes as u64);
+        Ok(JobChild {
+            child,
+            feeder,
+            cwd: self.relative(&req.cwd),
+            rlimits: *self.sandbox(&req.full).rlimits(),
+            permit: None,
+        })
+    }
+
+    /// Checks at startup that `cmd` names one of `allowed_cmds`.
+    pub fn check_allowed(&self, cmd: &str) -> anyhow::Result<()> {
+        if !self.allowed.contains(&resolve_cmd(cmd)?) {
+            anyhow::bail!("{cmd} is not in allowed_cmds");
+        }
+        Ok(())
+    }
+
+    fn command(&self, req: &ExecRequest, attach: Attach) -> Result<Command, AppError> {
+        use std::os::unix::process::CommandExt;
+        let mut command = Command::new(&req.full);
+        command.args(&req.args);
+        command.current_dir(&req.cwd);
+        #[cfg(target_os = "linux")]
+        if attach == Attach::Service {
+            let parent = std::process::id() as libc::pid_t;
+            // before the sandbox, whose PID namespace child is in turn tied to
+            // this one. The signal follows the spawning thread, which is a
+            // runtime worker that lives as long as Valet.
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    if libc::getppid() != parent {
+                        return Err(std::io::ErrorKind::BrokenPipe.into());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        if attach == Attach::Tty {
+            // before the sandbox, which may fork into namespaces
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        } else {
+            command.process_group(0);
+            command.stdin(req.stdin.stdio());
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+        }
         // env policy: clear then insert pass_env
         command.env_clear();
         for k in &self.pass_env {
@@ -91,12 +440,63 @@ impl Tool for ExecTool {
                 command.env(k, v);
             }
         }
+        self.sandbox(&req.full)
+            .apply(&mut command)
+            .map_err(|e| AppError::Internal(format!("sandbox setup failed: {e}")))?;
+        Ok(command)
+    }
+}
+
+fn resolve_cmds(cmds: &[String]) -> anyhow::Result<HashSet<PathBuf>> {
+    cmds.iter().map(|c| resolve_cmd(c)).collect()
+}
+
+fn resolve_cmd(cmd: &str) -> anyhow::Result<PathBuf> {
+    let path = if cmd.contains('/') {
+        PathBuf::from(cmd)
+    } else {
+        which::which(cmd)?
+    };
+    Ok(dunce::canonicalize(path)?)
+}
+
+#[async_trait]
+impl Tool for ExecTool {
+    fn capabilities(&self) -> serde_json::Value {
+        json!({"input": {"type":"object","required":["cmd"],"properties": {"cmd": {"type":"string"},"args":{"type":"array","items":{"type":"string"}},"timeout_s":{"type":"integer"},"cwd":{"type":"string"},"stdin_b64":{"type":"string"},"stdin_text":{"type":"string"},"stdin_path":{"type":"string"},"stdin_stream":{"type":"boolean"}}}, "output": {"type":"object","properties": {"exit_code":{"type":"integer"},"cwd":{"type":"string"},"stdout_b64":{"type":"string"},"stderr_b64":{"type":"string"},"duration_ms":{"type":"integer"},"cpu_ms":{"type":"integer"},"truncated":{"type":"boolean"},"timed_out":{"type":"boolean"},"termination":{"type":"string","enum":["exit","signal","seccomp","cpu_limit","file_size_limit","timeout","output_limit"]},"signal":{"type":["integer","null"]},"redactions":{"type":"integer"}}}})
+    }
+
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let req = self.prepare(ctx, &params)?;
+        if matches!(req.stdin, StdinSource::Stream) {
+            return Err(AppError::ToolError(
+                "stdin_stream needs a streaming call".into(),
+            ));
+        }
+        let timeout_s = req.timeout_s;
+        let cwd = self.relative(&req.cwd);
+        let queued = Instant::now();
+        let _permit = self
+            .queue
+            .enqueue(&ctx.principal.name)?
+            .wait(|_| {})
+            .await?;
+        let queued_ms = queued.elapsed().as_millis() as u64;
+        let mut command = self.command(&req, Attach::Pipes)?;
+        self.pins.pin(&mut command, &req.full)?;
 
         let start = Instant::now();
-        let mut child = match command.spawn() {
+        let mut child = match ChildProcess::spawn(&mut command) {
             Ok(c) => c,
-            Err(_) => return Err(AppError::Internal("failed to spawn".into())),
+            Err(e) => return Err(AppError::Internal(format!("failed to spawn: {e}"))),
         };
+        let feeder = req
+            .stdin
+            .feed(child.stdin.take(), None, self.max_stdin_bytes as u64);
 
         let mut stdout = child.stdout.take().unwrap();
         let mut stderr = child.stderr.take().unwrap();
@@ -109,19 +509,20 @@ impl Tool for ExecTool {
         let read_fut = async {
             let mut buf_out = [0u8; 8192];
             let mut buf_err = [0u8; 8192];
-            loop {
+            let (mut out_done, mut err_done) = (false, false);
+            while !(out_done && err_done) {
                 tokio::select! {
-                    r = stdout.read(&mut buf_out) => {
+                    r = stdout.read(&mut buf_out), if !out_done => {
                         let n = r.unwrap_or(0);
-                        if n == 0 { break; }
+                        if n == 0 { out_done = true; continue; }
                         out.extend_from_slice(&buf_out[..n]);
-                        if out.len() > max_bytes { truncated = true; let _ = child.kill().await; break; }
+                        if out.len() > max_bytes { truncated = true; break; }
                     }
-                    r = stderr.read(&mut buf_err) => {
+                    r = stderr.read(&mut buf_err), if !err_done => {
                         let n = r.unwrap_or(0);
-                        if n == 0 { continue; }
+                        if n == 0 { err_done = true; continue; }
                         err.extend_from_slice(&buf_err[..n]);
-                        if err.len() > max_bytes { truncated = true; let _ = child.kill().await; break; }
+                        if err.len() > max_bytes { truncated = true; break; }
                     }
                 }
             }
@@ -129,85 +530,105 @@ impl Tool for ExecTool {
 
         let to = Duration::from_secs(timeout_s);
         let timed_out = timeout(to, read_fut).await.is_err();
-        if timed_out {
-            let _ = child.kill().await;
+        if timed_out || truncated {
+            child.terminate(self.kill_grace).await;
         }
-        let status = match timeout(to, child.wait()).await {
-            Ok(Ok(s)) => s,
-            _ => return Err(AppError::ExecTimeout),
+        let exit = timeout(to, child.wait()).await;
+        // nothing the command started outlives the call
+        child.kill();
+        feeder.abort();
+        let duration_ms = start.elapsed().as_millis() as u64;
+        let exit = match exit {
+            Ok(Ok(e)) => e,
+            _ => {
+                ctx.record_usage(Usage {
+                    exec_wall_ms: duration_ms,
+                    ..Usage::default()
+                });
+                return Err(AppError::ExecTimeout);
+            }
         };
+        let cpu_ms = exit.cpu.as_millis() as u64;
+        ctx.record_usage(Usage {
+            exec_cpu_ms: cpu_ms,
+            exec_wall_ms: duration_ms,
+            ..Usage::default()
+        });
 
-        let duration_ms = start.elapsed().as_millis() as u64;
-        let exit_code = status.code().unwrap_or_default();
+        let exit_code = exit.status.code().unwrap_or_default();
+        let rlimits = self.sandbox(&req.full).rlimits();
+        let (termination, signal) = termination(&exit, timed_out, truncated, rlimits);
+        let (out, out_redactions) = self.redactor.redact(&out);
+        let (err, err_redactions) = self.redactor.redact(&err);
+        let redactions = out_redactions + err_redactions;
         let stdout_b64 = base64::engine::general_purpose::STANDARD.encode(&out);
         let stderr_b64 = base64::engine::general_purpose::STANDARD.encode(&err);
 
         Ok(
-            json!({"exit_code": exit_code, "stdout_b64": stdout_b64, "stderr_b64": stderr_b64, "duration_ms": duration_ms, "truncated": truncated, "timed_out": timed_out}),
+            json!({"exit_code": exit_code, "cwd": cwd, "stdout_b64": stdout_b64, "stderr_b64": stderr_b64, "duration_ms": duration_ms, "cpu_ms": cpu_ms, "truncated": truncated, "timed_out": timed_out, "termination": termination, "signal": signal, "queued_ms": queued_ms, "redactions": redactions}),
         )
     }
 
     async fn call_stream(
         &self,
+        ctx: &CallContext,
         params: serde_json::Value,
     ) -> Result<crate::server::StreamBody, AppError> {
         use futures::StreamExt;
         use tokio::sync::mpsc;
         use tokio_stream::wrappers::ReceiverStream;
 
-        let cmd = params
-            .get("cmd")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| AppError::ToolError("missing cmd".into()))?;
-        let args: Vec<String> = params
-            .get("args")
-            .and_then(|v| v.as_array())
-            .map(|a| {
-                a.iter()
-                    .filter_map(|x| x.as_str().map(|s| s.to_string()))
-                    .collect()
-            })
-            .unwrap_or_default();
-        let timeout_s = params
-            .get("timeout_s")
-            .and_then(|v| v.as_u64())
-            .map(|t| t.min(self.timeout_s))
-            .unwrap_or(self.timeout_s);
-
-        let full = if cmd.contains('/') {
-            dunce::canonicalize(cmd).map_err(|_| AppError::ExecDenied)?
-        } else {
-            which::which(cmd).map_err(|_| AppError::ExecDenied)?
+        let req = self.prepare(ctx, &params)?;
+        let timeout_s = req.timeout_s;
+        let cwd = self.relative(&req.cwd);
+        let ticket = self.queue.enqueue(&ctx.principal.name)?;
+        let mut command = self.command(&req, Attach::Pipes)?;
+        let stdin = req.stdin;
+        let (stdin_handle, chunks) = match stdin {
+            StdinSource::Stream => {
+                let (handle, chunks) = self.stdin_streams.open(&ctx.principal.name);
+                (Some(handle), Some(chunks))
+            }
+            _ => (None, None),
         };
-        let full = dunce::canonicalize(full).map_err(|_| AppError::ExecDenied)?;
-        if !self.allowed.contains(&full) {
-            return Err(AppError::ExecDenied);
-        }
-
-        let pass_env = self.pass_env.clone();
+        let max_stdin_bytes = self.max_stdin_bytes as u64;
         let max_bytes = self.max_stdout_kb * 1024;
+        let kill_grace = self.kill_grace;
+        let rlimits = *self.sandbox(&req.full).rlimits();
+        let (pins, full) = (self.pins.clone(), req.full.clone());
+        let ctx = ctx.clone();
+        let mut out_redactor = self.redactor.stream();
+        let mut err_redactor = self.redactor.stream();
 
         let (tx, rx) = mpsc::channel::<String>(32);
         tokio::spawn(async move {
-            let _ = tx.send(line(json!({"event":"start","tool":"exec"}))).await;
-            let mut command = Command::new(&full);
-            command.args(&args);
-            command.stdin(Stdio::null());
-            command.stdout(Stdio::piped());
-            command.stderr(Stdio::piped());
-            command.env_clear();
-            for k in &pass_env {
-                if let Ok(v) = std::env::var(k) {
-                    command.env(k, v);
+            let stdin_id = stdin_handle.as_ref().map(|h| h.id());
+            let _ = tx
+                .send(line(
+                    json!({"event":"start","tool":"exec","stdin_id": stdin_id}),
+                ))
+                .await;
+            let permit = ticket
+                .wait(|position| {
+                    let _ = tx.try_send(line(json!({"event":"queued","position": position})));
+                })
+                .await;
+            let _permit = match permit.and_then(|p| pins.pin(&mut command, &full).map(|_| p)) {
+                Ok(p) => p,
+                Err(e) => {
+                    let _ = tx.send(line(json!({"event":"error","error":{"code": e.code(),"message": e.to_string()}}))).await;
+                    return;
                 }
-            }
-            let mut child = match command.spawn() {
+            };
+            let start = Instant::now();
+            let mut child = match ChildProcess::spawn(&mut command) {
                 Ok(c) => c,
-                Err(_) => {
-                    let _ = tx.send(line(json!({"event":"error","error":{"code":"Internal","message":"failed to spawn"}}))).await;
+                Err(e) => {
+                    let _ = tx.send(line(json!({"event":"error","error":{"code":"Internal","message": format!("failed to spawn: {e}")}}))).await;
                     return;
                 }
             };
+            let feeder = stdin.feed(child.stdin.take(), chunks, max_stdin_bytes);
             let mut stdout = child.stdout.take().unwrap();
             let mut stderr = child.stderr.take().unwrap();
             let mut out_total = 0usize;
@@ -215,37 +636,59 @@ impl Tool for ExecTool {
             let mut buf_out = [0u8; 4096];
             let mut buf_err = [0u8; 4096];
             let to = Duration::from_secs(timeout_s);
-            let start = Instant::now();
+            let mut truncated = false;
             let read_fut = async {
-                loop {
+                let (mut out_done, mut err_done) = (false, false);
+                while !(out_done && err_done) {
                     tokio::select! {
-                        r = stdout.read(&mut buf_out) => {
+                        r = stdout.read(&mut buf_out), if !out_done => {
                             let n = r.unwrap_or(0);
-                            if n == 0 { break; }
+                            if n == 0 { out_done = true; continue; }
                             out_total += n;
-                            let b64 = base64::engine::general_purpose::STANDARD.encode(&buf_out[..n]);
-                            let _ = tx.send(line(json!({"event":"stdout","chunk_b64": b64}))).await;
-                            if out_total > max_bytes { let _ = child.kill().await; break; }
+                            send_chunk(&tx, "stdout", &out_redactor.push(&buf_out[..n])).await;
+                            if out_total > max_bytes { truncated = true; break; }
                         }
-                        r = stderr.read(&mut buf_err) => {
+                        r = stderr.read(&mut buf_err), if !err_done => {
                             let n = r.unwrap_or(0);
-                            if n == 0 { continue; }
+                            if n == 0 { err_done = true; continue; }
                             err_total += n;
-                            let b64 = base64::engine::general_purpose::STANDARD.encode(&buf_err[..n]);
-                            let _ = tx.send(line(json!({"event":"stderr","chunk_b64": b64}))).await;
-                            if err_total > max_bytes { let _ = child.kill().await; break; }
+                            send_chunk(&tx, "stderr", &err_redactor.push(&buf_err[..n])).await;
+                            if err_total > max_bytes { truncated = true; break; }
                         }
                     }
                 }
             };
-            let _ = timeout(to, read_fut).await;
+            let timed_out = timeout(to, read_fut).await.is_err();
+            if timed_out || truncated {
+                child.terminate(kill_grace).await;
+            }
+            send_chunk(&tx, "stdout", &out_redactor.finish()).await;
+            send_chunk(&tx, "stderr", &err_redactor.finish()).await;
+            let redactions = out_redactor.count + err_redactor.count;
             let status = timeout(to, child.wait()).await;
+            child.kill();
+            feeder.abort();
+            // later exec_stdin calls for this id fail with NotFound
+            drop(stdin_handle);
+            let duration_ms = start.elapsed().as_millis() as u64;
             match status {
-                Err(_) => {
-                    let _ = tx.send(line(json!({"event":"error","error":{"code":"ExecTimeout","message":"timeout"}}))).await;
+                Ok(Ok(exit)) => {
+                    let cpu_ms = exit.cpu.as_millis() as u64;
+                    ctx.record_usage(Usage {
+                        exec_cpu_ms: cpu_ms,
+                        exec_wall_ms: duration_ms,
+                        ..Usage::default()
+                    });
+                    let (termination, signal) = termination(&exit, timed_out, truncated, &rlimits);
+                    let _ = tx.send(line(json!({"event":"end","result": {"exit_code": exit.status.code().unwrap_or_default(), "cwd": cwd, "termination": termination, "signal": signal, "duration_ms": duration_ms, "cpu_ms": cpu_ms, "redactions": redactions }}))).await;
                 }
-                Ok(_) => {
-                    let _ = tx.send(line(json!({"event":"end","result": {"duration_ms": start.elapsed().as_millis() as u64 }}))).await;
+                _ => {
+                    child.kill();
+                    ctx.record_usage(Usage {
+                        exec_wall_ms: duration_ms,
+                        ..Usage::default()
+                    });
+                    let _ = tx.send(line(json!({"event":"error","error":{"code":"ExecTimeout","message":"timeout"}}))).await;
                 }
             }
         });
@@ -255,6 +698,45 @@ impl Tool for ExecTool {
     }
 }
 
+async fn send_chunk(tx: &tokio::sync::mpsc::Sender<String>, event: &str, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    let b64 = base64::engine::general_purpose::STANDARD.encode(data);
+    let _ = tx
+        .send(line(json!({"event": event, "chunk_b64": b64})))
+        .await;
+}
+
 fn line(v: serde_json::Value) -> String {
     format!("{v}\n")
 }
+
+/// Why the child stopped. Killing it ourselves takes precedence over the
+/// signal that did it; SIGSYS means the seccomp filter fired, SIGXCPU and
+/// SIGXFSZ (or SIGKILL at the hard CPU limit) an rlimit. The other rlimits
+/// only make syscalls fail, so they leave no trace to report.
+pub(crate) fn termination(
+    exit: &ExitInfo,
+    timed_out: bool,
+    truncated: bool,
+    rlimits: &Rlimits,
+) -> (&'static str, Option<i32>) {
+    let signal = exit.status.signal();
+    let over_cpu = rlimits.cpu_s.is_some_and(|s| exit.cpu.as_secs() >= s);
+    let reason = if timed_out {
+        "timeout"
+    } else if truncated {
+        "output_limit"
+    } else {
+        match signal {
+            Some(libc::SIGSYS) => "seccomp",
+            Some(libc::SIGXCPU) => "cpu_limit",
+            Some(libc::SIGKILL) if over_cpu => "cpu_limit",
+            Some(libc::SIGXFSZ) => "file_size_limit",
+            Some(_) => "signal",
+            None => "exit",
+        }
+    };
+    (reason, signal)
+}
@@ -0,0 +1,53 @@
+use crate::{
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    tools::stdin::StdinStreams,
+};
+use async_trait::async_trait;
+use base64::Engine;
+use serde_json::json;
+use std::sync::Arc;
+
+/// Feeds stdin to a streaming `exec` call started with `stdin_stream`.
+pub struct ExecStdinTool {
+    streams: Arc<StdinStreams>,
+}
+
+impl ExecStdinTool {
+    pub fn new(streams: Arc<StdinStreams>) -> Self {
+        Self { streams }
+    }
+}
+
+#[async_trait]
+impl Tool for ExecStdinTool {
+    fn capabilities(&self) -> serde_json::Value {
+        json!({"input": {"type":"object","required":["stdin_id"],"properties": {"stdin_id": {"type":"string"},"data_b64":{"type":"string"},"text":{"type":"string"},"eof":{"type":"boolean"}}}, "output": {"type":"object","properties": {"sent":{"type":"integer"}}}})
+    }
+
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let id = params
+            .get("stdin_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| AppError::ToolError("missing stdin_id".into()))?;
+        let data = match (params.get("data_b64"), params.get("text")) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::ToolError(
+                    "pass data_b64 or text, not both".into(),
+                ))
+            }
+            (Some(b64), None) => base64::engine::general_purpose::STANDARD
+                .decode(b64.as_str().unwrap_or_default())
+                .map_err(|_| AppError::ToolError("invalid base64".into()))?,
+            (None, Some(text)) => text.as_str().unwrap_or_default().as_bytes().to_vec(),
+            (None, None) => vec![],
+        };
+        let eof = params.get("eof").and_then(|v| v.as_bool()).unwrap_or(false);
+        let sent = self.streams.send(&ctx.principal.name, id, data, eof)?;
+        Ok(json!({"sent": sent}))
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{
+    config::{Access, Config, Permission},
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    tools::{
+        resolve::{Kind, Resolver},
+        PathPolicy,
+    },
+};
+use async_trait::async_trait;
+use serde_json::json;
+use std::path::Path;
+
+/// Entries returned per call; larger directories are cut off and flagged.
+const MAX_ENTRIES: usize = 1000;
+
+pub struct FsListTool {
+    resolver: Resolver,
+    policy: PathPolicy,
+}
+
+impl FsListTool {
+    pub fn new(cfg: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            resolver: Resolver::new(&cfg.root.root_dir, cfg.paths.symlinks)?,
+            policy: PathPolicy::new(cfg)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Tool for FsListTool {
+    fn capabilities(&self) -> serde_json::Value {
+        json!({"input": {"type":"object","properties": {"path": {"type":"string"}}}, "output": {"type":"object","properties": {"entries":{"type":"array","items":{"type":"object","properties":{"name":{"type":"string"},"type":{"type":"string"},"size":{"type":"integer"}}}},"truncated":{"type":"boolean"}}}})
+    }
+
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let path = params.get("path").and_then(|v| v.as_str()).unwrap_or(".");
+        let target = self.resolver.resolve(Path::new(path))?;
+        self.policy.check("fs_list", Access::List, &target.path)?;
+        self.policy
+            .permit(&ctx.principal.name, Permission::Read, &target.path)?;
+        let mut entries = vec![];
+        let mut truncated = false;
+        for (name, stat) in target.read_dir()? {
+            let kind = match stat.kind {
+                Kind::Symlink => "symlink",
+                Kind::Dir => "dir",
+                Kind::File | Kind::Other => "file",
+            };
+            // denied entries are left out rather than reported
+            let is_dir = stat.kind == Kind::Dir;
+            if !self
+                .policy
+                .allows("fs_list", Access::List, &target.path.join(&name), is_dir)
+            {
+                continue;
+            }
+            if entries.len() == MAX_ENTRIES {
+                truncated = true;
+                break;
+            }
+            entries.push(json!({
+                "name": name.to_string_lossy(),
+                "type": kind,
+                "size": stat.size,
+            }));
+        }
+        entries.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+        Ok(json!({"entries": entries, "truncated": truncated}))
+    }
+}
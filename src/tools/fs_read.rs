@@ -1,18 +1,30 @@
-use crate::{config::Config, errors::AppError, mcp::registry::Tool, tools::ensure_within_root};
+use crate::{
+    budget::Usage,
+    config::{Access, Config, Permission},
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    redact::Redactor,
+    tools::{resolve::Resolver, PathPolicy},
+};
 use async_trait::async_trait;
 use base64::Engine;
 use serde_json::json;
-use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
 
 pub struct FsReadTool {
-    root: PathBuf,
+    resolver: Resolver,
+    policy: PathPolicy,
+    redactor: Arc<Redactor>,
 }
 
 impl FsReadTool {
     pub fn new(cfg: &Config) -> anyhow::Result<Self> {
         Ok(Self {
-            root: cfg.root.root_dir.clone(),
+            resolver: Resolver::new(&cfg.root.root_dir, cfg.paths.symlinks)?,
+            policy: PathPolicy::new(cfg)?,
+            redactor: Arc::new(Redactor::new(cfg)?),
         })
     }
 }
@@ -20,23 +32,32 @@ impl FsReadTool {
 #[async_trait]
 impl Tool for FsReadTool {
     fn capabilities(&self) -> serde_json::Value {
-        json!({"input": {"type":"object","required":["path"],"properties": {"path": {"type":"string"}}}, "output": {"type":"object","properties": {"content_b64":{"type":"string"},"encoding":{"type":"string"}}}})
+        json!({"input": {"type":"object","required":["path"],"properties": {"path": {"type":"string"}}}, "output": {"type":"object","properties": {"content_b64":{"type":"string"},"encoding":{"type":"string"},"redactions":{"type":"integer"}}}})
     }
-    async fn call(&self, params: serde_json::Value) -> Result<serde_json::Value, AppError> {
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
         let path = params
             .get("path")
             .and_then(|v| v.as_str())
             .ok_or_else(|| AppError::ToolError("missing path".into()))?;
-        let full = ensure_within_root(&self.root, &PathBuf::from(path))
-            .map_err(|_| AppError::PathOutsideRoot)?;
-        let data = fs::read(&full).map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                AppError::NotFound
-            } else {
-                AppError::Internal(e.to_string())
-            }
-        })?;
+        let target = self.resolver.resolve(Path::new(path))?;
+        self.policy.check("fs_read", Access::Read, &target.path)?;
+        self.policy
+            .permit(&ctx.principal.name, Permission::Read, &target.path)?;
+        let mut data = vec![];
+        target
+            .open_read()?
+            .read_to_end(&mut data)
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        ctx.record_usage(Usage {
+            read_bytes: data.len() as u64,
+            ..Usage::default()
+        });
+        let (data, redactions) = self.redactor.redact(&data);
         let b64 = base64::engine::general_purpose::STANDARD.encode(data);
-        Ok(json!({"content_b64": b64, "encoding": "base64"}))
+        Ok(json!({"content_b64": b64, "encoding": "base64", "redactions": redactions}))
     }
 }
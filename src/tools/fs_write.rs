@@ -1,16 +1,24 @@
-use crate::{config::Config, errors::AppError, mcp::registry::Tool, tools::ensure_within_root};
+use crate::{
+    budget::Usage,
+    config::{Access, Config, Permission},
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    tools::{resolve::Resolver, PathPolicy},
+};
 use async_trait::async_trait;
 use base64::Engine;
 use serde_json::json;
-use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+use std::{fs, io::Write, os::unix::fs::PermissionsExt, path::Path};
 
 pub struct FsWriteTool {
-    root: PathBuf,
+    resolver: Resolver,
+    policy: PathPolicy,
 }
 impl FsWriteTool {
     pub fn new(cfg: &Config) -> anyhow::Result<Self> {
         Ok(Self {
-            root: cfg.root.root_dir.clone(),
+            resolver: Resolver::new(&cfg.root.root_dir, cfg.paths.symlinks)?,
+            policy: PathPolicy::new(cfg)?,
         })
     }
 }
@@ -20,7 +28,11 @@ impl Tool for FsWriteTool {
     fn capabilities(&self) -> serde_json::Value {
         json!({"input": {"type":"object","required":["path","content_b64"],"properties": {"path": {"type":"string"},"content_b64":{"type":"string"},"mode":{"type":"string"}}}, "output": {"type":"object","properties": {"bytes_written":{"type":"integer"}}}})
     }
-    async fn call(&self, params: serde_json::Value) -> Result<serde_json::Value, AppError> {
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
         let path = params
             .get("path")
             .and_then(|v| v.as_str())
@@ -30,19 +42,29 @@ impl Tool for FsWriteTool {
             .and_then(|v| v.as_str())
             .ok_or_else(|| AppError::ToolError("missing content_b64".into()))?;
         let mode = params.get("mode").and_then(|v| v.as_str());
-        let full = ensure_within_root(&self.root, &PathBuf::from(path))
-            .map_err(|_| AppError::PathOutsideRoot)?;
-        if let Some(parent) = full.parent() {
-            fs::create_dir_all(parent).map_err(|e| AppError::Internal(e.to_string()))?;
-        }
+        let target = self.resolver.resolve(Path::new(path))?;
+        self.policy.check("fs_write", Access::Write, &target.path)?;
+        let permission = if target.exists() {
+            Permission::Write
+        } else {
+            Permission::Create
+        };
+        self.policy
+            .permit(&ctx.principal.name, permission, &target.path)?;
         let bytes = base64::engine::general_purpose::STANDARD
             .decode(content_b64)
             .map_err(|_| AppError::ToolError("invalid base64".into()))?;
-        fs::write(&full, &bytes).map_err(|e| AppError::Internal(e.to_string()))?;
+        let mut file = target.create(0o666)?;
+        file.write_all(&bytes)
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        ctx.record_usage(Usage {
+            write_bytes: bytes.len() as u64,
+            ..Usage::default()
+        });
         if let Some(m) = mode {
             if let Ok(parsed) = u32::from_str_radix(m, 8) {
                 let perm = fs::Permissions::from_mode(parsed);
-                let _ = fs::set_permissions(&full, perm);
+                let _ = file.set_permissions(perm);
             }
         }
         Ok(json!({"bytes_written": bytes.len()}))
@@ -0,0 +1,509 @@
+use crate::{
+    budget::Usage,
+    config::Config,
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    redact::Redactor,
+    tools::exec::{termination, ExecTool, JobChild},
+};
+use async_trait::async_trait;
+use base64::Engine;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+use tokio::sync::Notify;
+
+/// Longest a `job_wait` may block.
+const MAX_WAIT_MS: u64 = 60_000;
+/// Largest page `job_output` returns.
+const MAX_PAGE: usize = 256 * 1024;
+
+/// Background jobs, shared by the `job_*` tools. Each job has a task that
+/// owns the child, collects its redacted output and stops it on `job_kill`,
+/// at its time limit or when it writes more than `max_output_kb`.
+pub struct Jobs {
+    exec: Arc<ExecTool>,
+    redactor: Arc<Redactor>,
+    max_running: usize,
+    max_running_per_principal: usize,
+    timeout_s: u64,
+    max_output: usize,
+    retention: Duration,
+    max_retained: usize,
+    kill_grace: Duration,
+    jobs: Mutex<Table>,
+}
+
+#[derive(Default)]
+struct Table {
+    jobs: HashMap<String, Arc<Job>>,
+    /// Principals of jobs past the cap check but not yet spawned, which count
+    /// as running so concurrent starts can't both take the last slot.
+    starting: Vec<String>,
+}
+
+/// A job slot taken under the table lock; given back if the start fails.
+struct Slot<'a> {
+    table: &'a Mutex<Table>,
+    principal: String,
+    filled: bool,
+}
+
+impl Slot<'_> {
+    fn fill(mut self, job: Arc<Job>) {
+        let mut table = self.table.lock().unwrap();
+        table.jobs.insert(job.id.clone(), job);
+        release(&mut table, &self.principal);
+        self.filled = true;
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if !self.filled {
+            release(&mut self.table.lock().unwrap(), &self.principal);
+        }
+    }
+}
+
+fn release(table: &mut Table, principal: &str) {
+    if let Some(i) = table.starting.iter().position(|p| p == principal) {
+        table.starting.swap_remove(i);
+    }
+}
+
+struct Job {
+    id: String,
+    principal: String,
+    cmd: String,
+    cwd: String,
+    started_at: u64,
+    start: Instant,
+    state: Mutex<JobState>,
+    /// Signalled when output arrives or the job finishes.
+    changed: Notify,
+    kill: Notify,
+}
+
+#[derive(Default)]
+struct JobState {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    redactions: usize,
+    result: Option<JobResult>,
+}
+
+struct JobResult {
+    finished: Instant,
+    exit_code: Option<i32>,
+    termination: &'static str,
+    signal: Option<i32>,
+    duration_ms: u64,
+    cpu_ms: u64,
+}
+
+/// Why the job's task stopped the child before it exited on its own.
+#[derive(PartialEq)]
+enum Stop {
+    Timeout,
+    Output,
+    Killed,
+}
+
+impl Jobs {
+    pub fn new(cfg: &Config, exec: Arc<ExecTool>) -> anyhow::Result<Self> {
+        let jobs = &cfg.jobs;
+        Ok(Self {
+            exec,
+            redactor: Arc::new(Redactor::new(cfg)?),
+            max_running: jobs.max_running,
+            max_running_per_principal: jobs.max_running_per_principal,
+            timeout_s: jobs.timeout_s,
+            max_output: jobs.max_output_kb * 1024,
+            retention: Duration::from_secs(jobs.retention_s),
+            max_retained: jobs.max_retained,
+            kill_grace: Duration::from_millis(cfg.limits.exec_kill_grace_ms),
+            jobs: Mutex::default(),
+        })
+    }
+
+    async fn start(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let principal = &ctx.principal.name;
+        let slot = {
+            let mut table = self.jobs.lock().unwrap();
+            self.prune(&mut table.jobs, principal);
+            let running = table
+                .jobs
+                .values()
+                .filter(|j| j.running())
+                .map(|j| &j.principal)
+                .chain(&table.starting);
+            let (all, mine) = running.fold((0, 0), |(all, mine), p| {
+                (all + 1, mine + usize::from(p == principal))
+            });
+            if all >= self.max_running || mine >= self.max_running_per_principal {
+                return Err(AppError::ToolError("too many running jobs".into()));
+            }
+            table.starting.push(principal.clone());
+            Slot {
+                table: &self.jobs,
+                principal: principal.clone(),
+                filled: false,
+            }
+        };
+        let timeout_s = params
+            .get("timeout_s")
+            .and_then(|v| v.as_u64())
+            .map(|t| t.min(self.timeout_s))
+            .unwrap_or(self.timeout_s);
+        let spawned = self.exec.spawn_job(ctx, params).await?;
+        let job = Arc::new(Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            principal: principal.clone(),
+            cmd: params["cmd"].as_str().unwrap_or_default().to_string(),
+            cwd: spawned.cwd.clone(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            start: Instant::now(),
+            state: Mutex::default(),
+            changed: Notify::new(),
+            kill: Notify::new(),
+        });
+        slot.fill(job.clone());
+        tokio::spawn(job.clone().run(
+            spawned,
+            ctx.clone(),
+            self.redactor.clone(),
+            Duration::from_secs(timeout_s),
+            self.max_output,
+            self.kill_grace,
+        ));
+        Ok(json!({"job_id": job.id, "cwd": job.cwd}))
+    }
+
+    /// Drops finished jobs past their retention, and the oldest of the
+    /// principal's finished jobs beyond `max_retained`.
+    fn prune(&self, jobs: &mut HashMap<String, Arc<Job>>, principal: &str) {
+        jobs.retain(|_, j| {
+            j.finished()
+                .is_none_or(|finished| finished.elapsed() < self.retention)
+        });
+        let mut done: Vec<_> = jobs
+            .values()
+            .filter(|j| j.principal == principal)
+            .filter_map(|j| Some((j.finished()?, j.id.clone())))
+            .collect();
+        done.sort();
+        let excess = done.len().saturating_sub(self.max_retained);
+        for (_, id) in done.into_iter().take(excess) {
+            jobs.remove(&id);
+        }
+    }
+
+    /// Looks up one of the caller's jobs.
+    fn get(&self, ctx: &CallContext, params: &serde_json::Value) -> Result<Arc<Job>, AppError> {
+        let id = params
+            .get("job_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| AppError::ToolError("missing job_id".into()))?;
+        let mut table = self.jobs.lock().unwrap();
+        self.prune(&mut table.jobs, &ctx.principal.name);
+        table
+            .jobs
+            .get(id)
+            .filter(|j| j.principal == ctx.principal.name)
+            .cloned()
+            .ok_or(AppError::NotFound)
+    }
+
+    async fn status(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        Ok(self.get(ctx, params)?.status())
+    }
+
+    async fn output(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let job = self.get(ctx, params)?;
+        let offset = params.get("offset").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+        let limit = params
+            .get("limit")
+            .and_then(|v| v.as_u64())
+            .map_or(MAX_PAGE, |l| (l as usize).min(MAX_PAGE));
+        let state = job.state.lock().unwrap();
+        let data = match params.get("stream").and_then(|v| v.as_str()) {
+            None | Some("stdout") => &state.stdout,
+            Some("stderr") => &state.stderr,
+            Some(_) => {
+                return Err(AppError::ToolError(
+                    "stream must be stdout or stderr".into(),
+                ))
+            }
+        };
+        let start = offset.min(data.len());
+        let end = start.saturating_add(limit).min(data.len());
+        Ok(json!({
+            "data_b64": base64::engine::general_purpose::STANDARD.encode(&data[start..end]),
+            "offset": start,
+            "next_offset": end,
+            "total": data.len(),
+            "eof": state.result.is_some() && end == data.len(),
+        }))
+    }
+
+    async fn wait(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let job = self.get(ctx, params)?;
+        let wait_ms = params
+            .get("timeout_ms")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(MAX_WAIT_MS)
+            .min(MAX_WAIT_MS);
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(wait_ms);
+        loop {
+            let changed = job.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if !job.running() {
+                break;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                break;
+            }
+        }
+        Ok(job.status())
+    }
+
+    async fn kill(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let job = self.get(ctx, params)?;
+        job.kill.notify_one();
+        let deadline = tokio::time::Instant::now() + self.kill_grace + Duration::from_secs(1);
+        loop {
+            let changed = job.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if !job.running() || tokio::time::timeout_at(deadline, changed).await.is_err() {
+                break;
+            }
+        }
+        Ok(job.status())
+    }
+}
+
+impl Job {
+    fn running(&self) -> bool {
+        self.state.lock().unwrap().result.is_none()
+    }
+
+    fn finished(&self) -> Option<Instant> {
+        self.state
+            .lock()
+            .unwrap()
+            .result
+            .as_ref()
+            .map(|r| r.finished)
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let result = state.result.as_ref();
+        json!({
+            "job_id": self.id,
+            "cmd": self.cmd,
+            "cwd": self.cwd,
+            "started_at": self.started_at,
+            "running": result.is_none(),
+            "elapsed_ms": result.map_or(self.start.elapsed().as_millis() as u64, |r| r.duration_ms),
+            "stdout_bytes": state.stdout.len(),
+            "stderr_bytes": state.stderr.len(),
+            "redactions": state.redactions,
+            "exit_code": result.and_then(|r| r.exit_code),
+            "termination": result.map(|r| r.termination),
+            "signal": result.and_then(|r| r.signal),
+            "cpu_ms": result.map(|r| r.cpu_ms),
+        })
+    }
+
+    fn append(&self, stderr: bool, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if stderr {
+            state.stderr.extend_from_slice(data);
+        } else {
+            state.stdout.extend_from_slice(data);
+        }
+        drop(state);
+        self.changed.notify_waiters();
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        spawned: JobChild,
+        ctx: CallContext,
+        redactor: Arc<Redactor>,
+        timeout: Duration,
+        max_output: usize,
+        kill_grace: Duration,
+    ) {
+        // the exec queue slot is released when the job ends
+        let JobChild {
+            mut child,
+            feeder,
+            rlimits,
+            permit: _permit,
+            ..
+        } = spawned;
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let mut out_redactor = redactor.stream();
+        let mut err_redactor = redactor.stream();
+        let (mut out_total, mut err_total) = (0usize, 0usize);
+        let (mut buf_out, mut buf_err) = ([0u8; 8192], [0u8; 8192]);
+        let (mut out_done, mut err_done) = (false, false);
+        let deadline = tokio::time::Instant::from_std(self.start) + timeout;
+        let mut stop = None;
+        let exit = loop {
+            tokio::select! {
+                r = stdout.read(&mut buf_out), if !out_done => {
+                    let n = r.unwrap_or(0);
+                    if n == 0 { out_done = true; continue; }
+                    out_total += n;
+                    self.append(false, &out_redactor.push(&buf_out[..n]));
+                    if out_total > max_output { stop = Some(Stop::Output); break None; }
+                }
+                r = stderr.read(&mut buf_err), if !err_done => {
+                    let n = r.unwrap_or(0);
+                    if n == 0 { err_done = true; continue; }
+                    err_total += n;
+                    self.append(true, &err_redactor.push(&buf_err[..n]));
+                    if err_total > max_output { stop = Some(Stop::Output); break None; }
+                }
+                exit = child.wait(), if out_done && err_done => break exit.ok(),
+                _ = tokio::time::sleep_until(deadline) => { stop = Some(Stop::Timeout); break None; }
+                _ = self.kill.notified() => { stop = Some(Stop::Killed); break None; }
+            }
+        };
+        let exit = match exit {
+            Some(exit) => Some(exit),
+            None => {
+                child.terminate(kill_grace).await;
+                child.wait().await.ok()
+            }
+        };
+        // nothing the job started outlives it
+        child.kill();
+        feeder.abort();
+        self.append(false, &out_redactor.finish());
+        self.append(true, &err_redactor.finish());
+        let duration_ms = self.start.elapsed().as_millis() as u64;
+        let cpu_ms = exit.map_or(0, |e| e.cpu.as_millis() as u64);
+        ctx.record_usage(Usage {
+            exec_cpu_ms: cpu_ms,
+            exec_wall_ms: duration_ms,
+            ..Usage::default()
+        });
+        let (reason, signal) = match &exit {
+            Some(exit) => termination(
+                exit,
+                stop == Some(Stop::Timeout),
+                stop == Some(Stop::Output),
+                &rlimits,
+            ),
+            None => ("signal", None),
+        };
+        let mut state = self.state.lock().unwrap();
+        state.redactions = out_redactor.count + err_redactor.count;
+        state.result = Some(JobResult {
+            finished: Instant::now(),
+            exit_code: exit.and_then(|e| e.status.code()),
+            termination: if stop == Some(Stop::Killed) {
+                "killed"
+            } else {
+                reason
+            },
+            signal,
+            duration_ms,
+            cpu_ms,
+        });
+        drop(state);
+        self.changed.notify_waiters();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum JobOp {
+    Start,
+    Status,
+    Output,
+    Wait,
+    Kill,
+}
+
+/// One of the `job_*` tools.
+pub struct JobTool {
+    jobs: Arc<Jobs>,
+    op: JobOp,
+}
+
+impl JobTool {
+    pub fn new(jobs: Arc<Jobs>, op: JobOp) -> Self {
+        Self { jobs, op }
+    }
+}
+
+#[async_trait]
+impl Tool for JobTool {
+    fn capabilities(&self) -> serde_json::Value {
+        let status = json!({"type":"object","properties": {"job_id":{"type":"string"},"cmd":{"type":"string"},"cwd":{"type":"string"},"started_at":{"type":"integer"},"running":{"type":"boolean"},"elapsed_ms":{"type":"integer"},"stdout_bytes":{"type":"integer"},"stderr_bytes":{"type":"integer"},"redactions":{"type":"integer"},"exit_code":{"type":["integer","null"]},"termination":{"type":["string","null"],"enum":["exit","signal","seccomp","cpu_limit","file_size_limit","timeout","output_limit","killed",null]},"signal":{"type":["integer","null"]},"cpu_ms":{"type":["integer","null"]}}});
+        match self.op {
+            JobOp::Start => {
+                json!({"input": {"type":"object","required":["cmd"],"properties": {"cmd": {"type":"string"},"args":{"type":"array","items":{"type":"string"}},"timeout_s":{"type":"integer"},"cwd":{"type":"string"},"stdin_b64":{"type":"string"},"stdin_text":{"type":"string"},"stdin_path":{"type":"string"}}}, "output": {"type":"object","properties": {"job_id":{"type":"string"},"cwd":{"type":"string"}}}})
+            }
+            JobOp::Status | JobOp::Kill => {
+                json!({"input": {"type":"object","required":["job_id"],"properties": {"job_id": {"type":"string"}}}, "output": status})
+            }
+            JobOp::Output => {
+                json!({"input": {"type":"object","required":["job_id"],"properties": {"job_id": {"type":"string"},"stream":{"type":"string","enum":["stdout","stderr"]},"offset":{"type":"integer"},"limit":{"type":"integer"}}}, "output": {"type":"object","properties": {"data_b64":{"type":"string"},"offset":{"type":"integer"},"next_offset":{"type":"integer"},"total":{"type":"integer"},"eof":{"type":"boolean"}}}})
+            }
+            JobOp::Wait => {
+                json!({"input": {"type":"object","required":["job_id"],"properties": {"job_id": {"type":"string"},"timeout_ms":{"type":"integer"}}}, "output": status})
+            }
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        match self.op {
+            JobOp::Start => self.jobs.start(ctx, &params).await,
+            JobOp::Status => self.jobs.status(ctx, &params).await,
+            JobOp::Output => self.jobs.output(ctx, &params).await,
+            JobOp::Wait => self.jobs.wait(ctx, &params).await,
+            JobOp::Kill => self.jobs.kill(ctx, &params).await,
+        }
+    }
+}
@@ -1,21 +1,126 @@
+pub mod args;
+pub mod budget_status;
 pub mod exec;
+pub mod exec_stdin;
+pub mod fs_list;
 pub mod fs_read;
 pub mod fs_write;
+pub mod jobs;
+pub mod pin;
+pub mod process;
+pub mod pty;
+pub mod queue;
+pub mod resolve;
+pub mod sandbox;
+pub mod services;
+pub mod stdin;
 
-use std::path::{Path, PathBuf};
+use crate::{
+    config::{Access, Config, Permission, PermissionRule},
+    errors::AppError,
+};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Component, Path, PathBuf};
 
-pub fn ensure_within_root(root: &Path, input: &Path) -> anyhow::Result<PathBuf> {
-    // allow relative or absolute inputs; join then canonicalize
-    let joined = if input.is_absolute() {
-        input.to_path_buf()
-    } else {
-        root.join(input)
-    };
-    let canon_root = dunce::canonicalize(root)?;
-    let canon_path = dunce::canonicalize(&joined)?;
-    if canon_path.starts_with(&canon_root) {
-        Ok(canon_path)
-    } else {
-        anyhow::bail!("path escapes root")
+/// Deny rules from config and `.valetignore`, plus path-scoped permissions,
+/// checked on paths from [`resolve::Resolver`].
+pub struct PathPolicy {
+    root: PathBuf,
+    rules: Vec<(Gitignore, Vec<Access>, Vec<String>)>,
+    permissions: Vec<PermissionRule>,
+}
+
+impl PathPolicy {
+    pub fn new(cfg: &Config) -> anyhow::Result<Self> {
+        let root = dunce::canonicalize(&cfg.root.root_dir)?;
+        let mut rules = vec![];
+        for rule in &cfg.paths.deny {
+            let mut builder = GitignoreBuilder::new(&root);
+            for glob in &rule.globs {
+                builder.add_line(None, glob)?;
+            }
+            rules.push((builder.build()?, rule.access.clone(), rule.tools.clone()));
+        }
+        let ignore_file = root.join(".valetignore");
+        if cfg.paths.valetignore && ignore_file.is_file() {
+            let mut builder = GitignoreBuilder::new(&root);
+            if let Some(e) = builder.add(&ignore_file) {
+                return Err(e.into());
+            }
+            let all = vec![Access::Read, Access::Write, Access::List];
+            rules.push((builder.build()?, all, vec!["*".to_string()]));
+        }
+        Ok(Self {
+            root,
+            rules,
+            permissions: cfg.paths.permissions.clone(),
+        })
+    }
+
+    /// Whether `tool` may touch `path` (already resolved under root) for `access`.
+    pub fn allows(&self, tool: &str, access: Access, path: &Path, is_dir: bool) -> bool {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if rel.as_os_str().is_empty() {
+            return true;
+        }
+        // the ignore file can't be used to lift its own rules
+        if access == Access::Write && rel == Path::new(".valetignore") {
+            return false;
+        }
+        !self.rules.iter().any(|(matcher, accesses, tools)| {
+            accesses.contains(&access)
+                && tools.iter().any(|t| t == "*" || t == tool)
+                && matcher.matched_path_or_any_parents(rel, is_dir).is_ignore()
+        })
+    }
+
+    pub fn check(&self, tool: &str, access: Access, path: &Path) -> Result<(), AppError> {
+        if self.allows(tool, access, path, path.is_dir()) {
+            Ok(())
+        } else {
+            Err(AppError::PathDenied)
+        }
+    }
+
+    /// Checks `permission` for `principal` on `path` (already resolved under
+    /// root). A matching rule that names the principal takes precedence over
+    /// rules for everyone; within each group the longest path wins.
+    pub fn permit(
+        &self,
+        principal: &str,
+        permission: Permission,
+        path: &Path,
+    ) -> Result<(), AppError> {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return Err(AppError::PermissionDenied(permission.as_str()));
+        };
+        let best = |own: bool| {
+            self.permissions
+                .iter()
+                .filter(|r| {
+                    if own {
+                        r.principals.iter().any(|p| p == principal)
+                    } else {
+                        r.principals.is_empty()
+                    }
+                })
+                .filter(|r| rel.starts_with(normalized(&r.path)))
+                .max_by_key(|r| normalized(&r.path).components().count())
+        };
+        match best(true).or_else(|| best(false)) {
+            Some(rule) if !rule.allow.contains(&permission) => {
+                Err(AppError::PermissionDenied(permission.as_str()))
+            }
+            _ => Ok(()),
+        }
     }
 }
+
+/// Rule path without `.` components, so `.` and `./src` compare as prefixes.
+fn normalized(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
@@ -0,0 +1,170 @@
+use crate::errors::AppError;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// What identifies a file's contents without reading it. Replacing the file
+/// changes the inode; rewriting it in place changes mtime and ctime, and
+/// ctime can't be set back from user space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_ns: i128,
+    ctime_ns: i128,
+}
+
+impl FileId {
+    fn of(meta: &std::fs::Metadata) -> Self {
+        let ns = |s: i64, n: i64| s as i128 * 1_000_000_000 + n as i128;
+        Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            size: meta.size(),
+            mtime_ns: ns(meta.mtime(), meta.mtime_nsec()),
+            ctime_ns: ns(meta.ctime(), meta.ctime_nsec()),
+        }
+    }
+}
+
+/// Pinned sha256 digests of allowed binaries, checked right before every
+/// spawn. A binary is only hashed again when its [`FileId`] changes.
+#[derive(Debug, Default)]
+pub struct Pins {
+    digests: HashMap<PathBuf, Vec<u8>>,
+    verified: Mutex<HashMap<PathBuf, FileId>>,
+}
+
+impl Pins {
+    pub fn new(digests: HashMap<PathBuf, Vec<u8>>) -> Self {
+        Self {
+            digests,
+            verified: Mutex::default(),
+        }
+    }
+
+    /// Verifies a pinned `command`'s binary and makes it run from the file
+    /// that was checked rather than opening the path again. Call it last,
+    /// after everything else is set up; the command must have `env_clear`ed.
+    pub fn pin(&self, command: &mut Command, path: &Path) -> Result<(), AppError> {
+        if let Some(file) = self.verify(path)? {
+            exec_file(command, file)
+                .map_err(|e| AppError::Internal(format!("failed to pin binary: {e}")))?;
+        }
+        Ok(())
+    }
+
+    /// The opened binary if it is pinned and its contents match; None if it
+    /// isn't pinned.
+    pub fn verify(&self, path: &Path) -> Result<Option<File>, AppError> {
+        let Some(want) = self.digests.get(path) else {
+            return Ok(None);
+        };
+        let mut file = File::open(path).map_err(|_| AppError::ExecDenied)?;
+        let id = FileId::of(&file.metadata().map_err(|_| AppError::ExecDenied)?);
+        if self.verified.lock().unwrap().get(path) == Some(&id) {
+            return Ok(Some(file));
+        }
+        let digest = sha256(&mut file).map_err(|e| AppError::Internal(e.to_string()))?;
+        if digest != *want {
+            tracing::warn!(path = %path.display(), "pinned binary changed");
+            self.verified.lock().unwrap().remove(path);
+            return Err(AppError::BinaryMismatch);
+        }
+        self.verified.lock().unwrap().insert(path.to_path_buf(), id);
+        Ok(Some(file))
+    }
+}
+
+/// Adds a final `pre_exec` step that execs `file` with `fexecve`, so what runs
+/// is what was hashed even if the path is swapped in between. Arguments and
+/// environment are taken from `command`, which std would otherwise pass itself.
+#[cfg(target_os = "linux")]
+fn exec_file(command: &mut Command, file: File) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    /// Pointers into the owned strings, built before fork so the child
+    /// doesn't allocate.
+    struct Exec {
+        _strings: Vec<CString>,
+        argv: Vec<*const libc::c_char>,
+        envp: Vec<*const libc::c_char>,
+        file: File,
+    }
+    // the pointers only refer to `_strings`, which moves along with them
+    unsafe impl Send for Exec {}
+    unsafe impl Sync for Exec {}
+
+    impl Exec {
+        /// Only returns on failure.
+        fn run(&self) -> io::Error {
+            let fd = self.file.as_raw_fd();
+            unsafe {
+                libc::fexecve(fd, self.argv.as_ptr(), self.envp.as_ptr());
+                // a script's interpreter opens it as /dev/fd/N, which needs
+                // the descriptor to survive the exec
+                if io::Error::last_os_error().raw_os_error() == Some(libc::ENOENT)
+                    && libc::fcntl(fd, libc::F_SETFD, 0) == 0
+                {
+                    libc::fexecve(fd, self.argv.as_ptr(), self.envp.as_ptr());
+                }
+            }
+            io::Error::last_os_error()
+        }
+    }
+
+    let cstr = |b: &[u8]| CString::new(b).map_err(io::Error::from);
+    let args = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|a| cstr(a.as_bytes()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let env = command
+        .get_envs()
+        .filter_map(|(k, v)| v.map(|v| [k.as_bytes(), b"=", v.as_bytes()].concat()))
+        .map(|kv| cstr(&kv))
+        .collect::<io::Result<Vec<_>>>()?;
+    let nul = |v: &[CString]| {
+        v.iter()
+            .map(|s| s.as_ptr())
+            .chain([std::ptr::null()])
+            .collect::<Vec<_>>()
+    };
+    let (argv, envp) = (nul(&args), nul(&env));
+    let exec = Exec {
+        _strings: args.into_iter().chain(env).collect(),
+        argv,
+        envp,
+        file,
+    };
+    unsafe {
+        command.pre_exec(move || Err(exec.run()));
+    }
+    Ok(())
+}
+
+/// Without `fexecve` the path is opened again, after the check.
+#[cfg(not(target_os = "linux"))]
+fn exec_file(_command: &mut Command, _file: File) -> io::Result<()> {
+    Ok(())
+}
+
+fn sha256(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        ctx.update(&buf[..n]);
+    }
+    Ok(ctx.finish().as_ref().to_vec())
+}
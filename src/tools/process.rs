@@ -0,0 +1,141 @@
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::{ChildStderr, ChildStdin, ChildStdout};
+use tokio::task::JoinHandle;
+
+/// How a child ended, with the CPU time the kernel charged to it.
+#[derive(Debug, Clone, Copy)]
+pub struct ExitInfo {
+    pub status: ExitStatus,
+    pub cpu: Duration,
+}
+
+/// A spawned child reaped with `wait4` on a blocking thread, so its resource usage
+/// can be attributed to this call even when several children run concurrently.
+/// Callers make the child lead its own process group (or session), so signals
+/// sent to the group reach everything it started. Whatever is left in the group
+/// when the leader exits is killed before the leader is reaped.
+pub struct ChildProcess {
+    pid: libc::pid_t,
+    /// Set once the leader is reaped, after which its pid (and so the group id)
+    /// may belong to someone else. Held while signalling so reaping waits.
+    reaped: Arc<Mutex<bool>>,
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    reaper: JoinHandle<io::Result<ExitInfo>>,
+    exit: Option<ExitInfo>,
+}
+
+impl ChildProcess {
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        let mut child = command.spawn()?;
+        let pid = child.id() as libc::pid_t;
+        let stdin = child.stdin.take().map(ChildStdin::from_std).transpose()?;
+        let stdout = child.stdout.take().map(ChildStdout::from_std).transpose()?;
+        let stderr = child.stderr.take().map(ChildStderr::from_std).transpose()?;
+        let reaped = Arc::new(Mutex::new(false));
+        let flag = reaped.clone();
+        let reaper = tokio::task::spawn_blocking(move || reap(pid, &flag));
+        Ok(Self {
+            pid,
+            reaped,
+            stdin,
+            stdout,
+            stderr,
+            reaper,
+            exit: None,
+        })
+    }
+
+    /// Sends SIGKILL to the whole process group.
+    pub fn kill(&self) {
+        self.signal_group(libc::SIGKILL);
+    }
+
+    /// Sends SIGTERM to the process group, gives it `grace` to exit, then
+    /// SIGKILLs whatever is left.
+    pub async fn terminate(&self, grace: Duration) {
+        self.signal_group(libc::SIGTERM);
+        let deadline = tokio::time::Instant::now() + grace;
+        while self.group_alive() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        self.signal_group(libc::SIGKILL);
+    }
+
+    /// Does nothing once the leader is reaped: the reaper has already killed
+    /// the group, and the id may have been recycled.
+    fn signal_group(&self, signal: libc::c_int) {
+        let reaped = self.reaped.lock().unwrap();
+        if !*reaped {
+            unsafe {
+                libc::killpg(self.pid, signal);
+            }
+        }
+    }
+
+    /// Whether the leader is unreaped or anything else is left in the group.
+    fn group_alive(&self) -> bool {
+        let reaped = self.reaped.lock().unwrap();
+        !*reaped && unsafe { libc::killpg(self.pid, 0) == 0 }
+    }
+
+    /// Waits for exit. Cancel-safe: it can be retried after a timeout.
+    pub async fn wait(&mut self) -> io::Result<ExitInfo> {
+        if let Some(e) = self.exit {
+            return Ok(e);
+        }
+        let info = (&mut self.reaper).await.map_err(io::Error::other)??;
+        self.exit = Some(info);
+        Ok(info)
+    }
+}
+
+/// Waits for the leader to exit without reaping it, kills what is left of its
+/// group while the id is still pinned by the zombie leader, then reaps it.
+fn reap(pid: libc::pid_t, reaped: &Mutex<bool>) -> io::Result<ExitInfo> {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) } == 0 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            // gone already, so the id is no longer ours to signal
+            *reaped.lock().unwrap() = true;
+            return Err(err);
+        }
+    }
+    let mut reaped = reaped.lock().unwrap();
+    unsafe {
+        libc::killpg(pid, libc::SIGKILL);
+    }
+    let exit = wait4(pid);
+    *reaped = true;
+    exit
+}
+
+fn wait4(pid: libc::pid_t) -> io::Result<ExitInfo> {
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        let r = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+        if r == pid {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    let tv = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    Ok(ExitInfo {
+        status: ExitStatus::from_raw(status),
+        cpu: tv(usage.ru_utime) + tv(usage.ru_stime),
+    })
+}
@@ -0,0 +1,570 @@
+use crate::{
+    budget::Usage,
+    config::Config,
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    redact::{Redactor, StreamRedactor},
+    tools::{
+        exec::ExecTool,
+        process::{ChildProcess, ExitInfo},
+        sandbox::cvt,
+    },
+};
+use async_trait::async_trait;
+use base64::Engine;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Longest a `pty_read` may wait for output.
+const MAX_WAIT_MS: u64 = 30_000;
+/// Longest a `pty_write` waits for the terminal to accept input.
+const WRITE_TIMEOUT_MS: i32 = 5_000;
+/// How long the terminal must be quiet before a held-back partial line, such
+/// as a prompt, is redacted and made readable.
+const QUIET_MS: i32 = 100;
+/// Longest an exited session waits for the reader to flush the last output.
+const DRAIN_MS: u64 = 500;
+
+/// Open terminal sessions, shared by the `pty_*` tools. Each session has a
+/// thread reading the terminal into a capped scrollback buffer and a task
+/// that owns the child and ends it on `pty_close` or after the idle timeout.
+pub struct PtySessions {
+    exec: Arc<ExecTool>,
+    redactor: Arc<Redactor>,
+    max_sessions: usize,
+    max_per_principal: usize,
+    idle_timeout: Duration,
+    scrollback: usize,
+    term: String,
+    kill_grace: Duration,
+    sessions: Mutex<Table>,
+}
+
+#[derive(Default)]
+struct Table {
+    sessions: HashMap<String, Arc<Session>>,
+    /// Principals of sessions past the cap check but not yet spawned, which
+    /// count as open so concurrent opens can't both take the last slot.
+    starting: Vec<String>,
+}
+
+/// A session slot taken under the table lock; given back if the open fails.
+struct Slot<'a> {
+    table: &'a Mutex<Table>,
+    principal: String,
+    filled: bool,
+}
+
+impl Slot<'_> {
+    fn fill(mut self, id: String, session: Arc<Session>) {
+        let mut table = self.table.lock().unwrap();
+        table.sessions.insert(id, session);
+        release(&mut table, &self.principal);
+        self.filled = true;
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if !self.filled {
+            release(&mut self.table.lock().unwrap(), &self.principal);
+        }
+    }
+}
+
+fn release(table: &mut Table, principal: &str) {
+    if let Some(i) = table.starting.iter().position(|p| p == principal) {
+        table.starting.swap_remove(i);
+    }
+}
+
+struct Session {
+    principal: String,
+    master: File,
+    output: Mutex<Output>,
+    /// Signalled when output arrives or the child exits.
+    changed: Notify,
+    close: Notify,
+    last_used: Mutex<Instant>,
+}
+
+#[derive(Default)]
+struct Output {
+    unread: VecDeque<u8>,
+    /// Bytes that fell out of the scrollback before anyone read them.
+    dropped: u64,
+    /// Secrets redacted from output not yet read.
+    redactions: usize,
+    /// Set once the terminal is closed and all its output is in `unread`.
+    eof: bool,
+    exit: Option<ExitInfo>,
+}
+
+impl PtySessions {
+    pub fn new(cfg: &Config, exec: Arc<ExecTool>) -> anyhow::Result<Self> {
+        Ok(Self {
+            exec,
+            redactor: Arc::new(Redactor::new(cfg)?),
+            max_sessions: cfg.pty.max_sessions,
+            max_per_principal: cfg.pty.max_sessions_per_principal,
+            idle_timeout: Duration::from_secs(cfg.pty.idle_timeout_s),
+            scrollback: cfg.pty.scrollback_kb * 1024,
+            term: cfg.pty.term.clone(),
+            kill_grace: Duration::from_millis(cfg.limits.exec_kill_grace_ms),
+            sessions: Mutex::default(),
+        })
+    }
+
+    async fn open(
+        self: &Arc<Self>,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let principal = &ctx.principal.name;
+        let slot = {
+            let mut table = self.sessions.lock().unwrap();
+            let open = table
+                .sessions
+                .values()
+                .map(|s| &s.principal)
+                .chain(&table.starting);
+            let (all, mine) = open.fold((0, 0), |(all, mine), p| {
+                (all + 1, mine + usize::from(p == principal))
+            });
+            if all >= self.max_sessions || mine >= self.max_per_principal {
+                return Err(AppError::ToolError("too many open PTY sessions".into()));
+            }
+            table.starting.push(principal.clone());
+            Slot {
+                table: &self.sessions,
+                principal: principal.clone(),
+                filled: false,
+            }
+        };
+        let (mut command, cwd) = self.exec.pty_command(ctx, params)?;
+        let (master, slave) = open_pty(size(params)?)
+            .map_err(|e| AppError::Internal(format!("openpty failed: {e}")))?;
+        let stdio = |fd: &OwnedFd| {
+            fd.try_clone()
+                .map(Stdio::from)
+                .map_err(|e| AppError::Internal(e.to_string()))
+        };
+        command.stdin(stdio(&slave)?);
+        command.stdout(stdio(&slave)?);
+        command.stderr(Stdio::from(slave));
+        command.env("TERM", &self.term);
+        let start = Instant::now();
+        let child = ChildProcess::spawn(&mut command)
+            .map_err(|e| AppError::Internal(format!("failed to spawn: {e}")))?;
+        // the child holds the only copies of the terminal's slave side now
+        drop(command);
+
+        let reader = master
+            .try_clone()
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        let session = Arc::new(Session {
+            principal: principal.clone(),
+            master: File::from(master),
+            output: Mutex::default(),
+            changed: Notify::new(),
+            close: Notify::new(),
+            last_used: Mutex::new(Instant::now()),
+        });
+        let id = uuid::Uuid::new_v4().to_string();
+        slot.fill(id.clone(), session.clone());
+        let scrollback = self.scrollback;
+        let stream = self.redactor.stream();
+        let s = session.clone();
+        std::thread::spawn(move || s.read_loop(File::from(reader), stream, scrollback));
+        tokio::spawn(
+            self.clone()
+                .supervise(id.clone(), session, child, ctx.clone(), start),
+        );
+        Ok(json!({"session_id": id, "cwd": cwd}))
+    }
+
+    /// Owns the child: waits for it to exit, ending it early on close or
+    /// when idle, then drops the session once it has sat idle again.
+    async fn supervise(
+        self: Arc<Self>,
+        id: String,
+        session: Arc<Session>,
+        mut child: ChildProcess,
+        ctx: CallContext,
+        start: Instant,
+    ) {
+        let exit = loop {
+            let deadline = *session.last_used.lock().unwrap() + self.idle_timeout;
+            tokio::select! {
+                exit = child.wait() => break exit,
+                _ = session.close.notified() => child.terminate(self.kill_grace).await,
+                _ = tokio::time::sleep_until(deadline.into()) => {
+                    if session.idle_for() >= self.idle_timeout {
+                        tracing::info!(session_id = %id, "closing idle PTY session");
+                        self.sessions.lock().unwrap().sessions.remove(&id);
+                        child.terminate(self.kill_grace).await;
+                    }
+                }
+            }
+        };
+        child.kill();
+        // let the reader flush what the redactor held back before the exit
+        // shows, so a read that sees the exit also sees the last output
+        let drained = async {
+            loop {
+                let changed = session.changed.notified();
+                if session.output.lock().unwrap().eof {
+                    break;
+                }
+                changed.await;
+            }
+        };
+        let _ = tokio::time::timeout(Duration::from_millis(DRAIN_MS), drained).await;
+        if let Ok(exit) = exit {
+            ctx.record_usage(Usage {
+                exec_cpu_ms: exit.cpu.as_millis() as u64,
+                exec_wall_ms: start.elapsed().as_millis() as u64,
+                ..Usage::default()
+            });
+            session.output.lock().unwrap().exit = Some(exit);
+        }
+        session.changed.notify_waiters();
+        // keep the exit status and final output around for a last read
+        loop {
+            let deadline = *session.last_used.lock().unwrap() + self.idle_timeout;
+            tokio::select! {
+                _ = session.close.notified() => break,
+                _ = tokio::time::sleep_until(deadline.into()) => {
+                    if session.idle_for() >= self.idle_timeout {
+                        break;
+                    }
+                }
+            }
+        }
+        self.sessions.lock().unwrap().sessions.remove(&id);
+    }
+
+    /// Looks up one of the caller's sessions and marks it as used.
+    fn get(&self, ctx: &CallContext, params: &serde_json::Value) -> Result<Arc<Session>, AppError> {
+        let id = params
+            .get("session_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| AppError::ToolError("missing session_id".into()))?;
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .get(id)
+            .filter(|s| s.principal == ctx.principal.name)
+            .cloned()
+            .ok_or(AppError::NotFound)?;
+        *session.last_used.lock().unwrap() = Instant::now();
+        Ok(session)
+    }
+
+    async fn write(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let session = self.get(ctx, params)?;
+        let data = match (params.get("text"), params.get("data_b64")) {
+            (Some(t), None) => t.as_str().unwrap_or_default().as_bytes().to_vec(),
+            (None, Some(b)) => base64::engine::general_purpose::STANDARD
+                .decode(b.as_str().unwrap_or_default())
+                .map_err(|_| AppError::ToolError("invalid base64".into()))?,
+            _ => return Err(AppError::ToolError("pass text or data_b64".into())),
+        };
+        if session.output.lock().unwrap().exit.is_some() {
+            return Err(AppError::ToolError("session has exited".into()));
+        }
+        let len = data.len();
+        tokio::task::spawn_blocking(move || session.write_input(&data))
+            .await
+            .map_err(|e| AppError::Internal(e.to_string()))?
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::TimedOut => {
+                    AppError::ToolError("terminal is not reading input".into())
+                }
+                _ => AppError::Internal(e.to_string()),
+            })?;
+        Ok(json!({"written": len}))
+    }
+
+    async fn read(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let session = self.get(ctx, params)?;
+        let wait_ms = params
+            .get("wait_ms")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0)
+            .min(MAX_WAIT_MS);
+        let changed = session.changed.notified();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+        let idle = {
+            let out = session.output.lock().unwrap();
+            out.unread.is_empty() && out.exit.is_none()
+        };
+        if idle && wait_ms > 0 {
+            let _ = tokio::time::timeout(Duration::from_millis(wait_ms), changed).await;
+        }
+        let mut out = session.output.lock().unwrap();
+        let data: Vec<u8> = out.unread.drain(..).collect();
+        let dropped = std::mem::take(&mut out.dropped);
+        let redactions = std::mem::take(&mut out.redactions);
+        let exit = out.exit;
+        drop(out);
+        Ok(json!({
+            "data_b64": base64::engine::general_purpose::STANDARD.encode(&data),
+            "dropped": dropped,
+            "redactions": redactions,
+            "exited": exit.is_some(),
+            "exit_code": exit.and_then(|e| e.status.code()),
+            "signal": exit.and_then(|e| e.status.signal()),
+        }))
+    }
+
+    async fn resize(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let session = self.get(ctx, params)?;
+        let ws = size(params)?;
+        cvt(unsafe { libc::ioctl(session.master.as_raw_fd(), libc::TIOCSWINSZ as _, &ws) })
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        Ok(json!({"cols": ws.ws_col, "rows": ws.ws_row}))
+    }
+
+    async fn close(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let session = self.get(ctx, params)?;
+        session.close.notify_one();
+        // `changed` also fires for output, so wait for the exit itself
+        let exited = async {
+            loop {
+                let changed = session.changed.notified();
+                if session.output.lock().unwrap().exit.is_some() {
+                    break;
+                }
+                changed.await;
+            }
+        };
+        let wait = self.kill_grace + Duration::from_millis(DRAIN_MS) + Duration::from_secs(1);
+        let _ = tokio::time::timeout(wait, exited).await;
+        // wakes the supervisor's post-exit wait too
+        session.close.notify_one();
+        let exit = session.output.lock().unwrap().exit;
+        Ok(json!({
+            "closed": true,
+            "exit_code": exit.and_then(|e| e.status.code()),
+            "signal": exit.and_then(|e| e.status.signal()),
+        }))
+    }
+}
+
+impl Session {
+    fn idle_for(&self) -> Duration {
+        self.last_used.lock().unwrap().elapsed()
+    }
+
+    /// Runs until every process holding the terminal has exited, when reads
+    /// fail with EIO. Output is redacted as it arrives; the tail the redactor
+    /// holds back is let out once the terminal goes quiet, and at the end.
+    fn read_loop(&self, mut master: File, mut stream: StreamRedactor, scrollback: usize) {
+        let mut buf = [0u8; 4096];
+        loop {
+            if stream.holding() && !readable(&master, QUIET_MS) {
+                self.append(&stream.finish(), &mut stream, scrollback);
+                continue;
+            }
+            let n = match master.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            self.append(&stream.push(&buf[..n]), &mut stream, scrollback);
+        }
+        self.append(&stream.finish(), &mut stream, scrollback);
+        self.output.lock().unwrap().eof = true;
+        self.changed.notify_waiters();
+    }
+
+    fn append(&self, data: &[u8], stream: &mut StreamRedactor, scrollback: usize) {
+        let mut out = self.output.lock().unwrap();
+        out.unread.extend(data);
+        let excess = out.unread.len().saturating_sub(scrollback);
+        out.unread.drain(..excess);
+        out.dropped += excess as u64;
+        out.redactions += std::mem::take(&mut stream.count);
+        drop(out);
+        self.changed.notify_waiters();
+    }
+
+    fn write_input(&self, mut data: &[u8]) -> io::Result<()> {
+        let fd = self.master.as_raw_fd();
+        while !data.is_empty() {
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            match unsafe { libc::poll(&mut pfd, 1, WRITE_TIMEOUT_MS) } {
+                0 => return Err(io::ErrorKind::TimedOut.into()),
+                n if n < 0 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(e);
+                }
+                _ => {}
+            }
+            let n = (&self.master).write(data)?;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+}
+
+/// Whether the terminal has output within `timeout_ms`. Errors count as
+/// readable so the following read reports them.
+fn readable(master: &File, timeout_ms: i32) -> bool {
+    let mut pfd = libc::pollfd {
+        fd: master.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        match unsafe { libc::poll(&mut pfd, 1, timeout_ms) } {
+            0 => return false,
+            n if n < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            _ => return true,
+        }
+    }
+}
+
+fn size(params: &serde_json::Value) -> Result<libc::winsize, AppError> {
+    let dim = |key: &str, default: u16| match params.get(key).and_then(|v| v.as_u64()) {
+        None => Ok(default),
+        Some(n @ 1..=1000) => Ok(n as u16),
+        Some(_) => Err(AppError::ToolError(format!(
+            "{key} must be between 1 and 1000"
+        ))),
+    };
+    Ok(libc::winsize {
+        ws_col: dim("cols", 80)?,
+        ws_row: dim("rows", 24)?,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    })
+}
+
+/// A new pseudo-terminal as (master, slave), both close-on-exec.
+#[cfg(target_os = "linux")]
+fn open_pty(ws: libc::winsize) -> io::Result<(OwnedFd, OwnedFd)> {
+    // opened close-on-exec so children spawned concurrently can't inherit them
+    let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+    let master = cvt(unsafe { libc::posix_openpt(flags) })?;
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    cvt(unsafe { libc::unlockpt(master.as_raw_fd()) })?;
+    let slave = cvt(unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGPTPEER as _, flags) })?;
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    cvt(unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ as _, &ws) })?;
+    Ok((master, slave))
+}
+
+/// A new pseudo-terminal as (master, slave), both close-on-exec.
+#[cfg(not(target_os = "linux"))]
+fn open_pty(ws: libc::winsize) -> io::Result<(OwnedFd, OwnedFd)> {
+    let (mut master, mut slave) = (-1, -1);
+    cvt(unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &ws as *const _ as *mut _,
+        )
+    })?;
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    for fd in [&master, &slave] {
+        cvt(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    }
+    Ok((master, slave))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PtyOp {
+    Open,
+    Write,
+    Read,
+    Resize,
+    Close,
+}
+
+/// One of the `pty_*` tools.
+pub struct PtyTool {
+    sessions: Arc<PtySessions>,
+    op: PtyOp,
+}
+
+impl PtyTool {
+    pub fn new(sessions: Arc<PtySessions>, op: PtyOp) -> Self {
+        Self { sessions, op }
+    }
+}
+
+#[async_trait]
+impl Tool for PtyTool {
+    fn capabilities(&self) -> serde_json::Value {
+        match self.op {
+            PtyOp::Open => {
+                json!({"input": {"type":"object","required":["cmd"],"properties": {"cmd": {"type":"string"},"args":{"type":"array","items":{"type":"string"}},"cwd":{"type":"string"},"cols":{"type":"integer"},"rows":{"type":"integer"}}}, "output": {"type":"object","properties": {"session_id":{"type":"string"},"cwd":{"type":"string"}}}})
+            }
+            PtyOp::Write => {
+                json!({"input": {"type":"object","required":["session_id"],"properties": {"session_id": {"type":"string"},"text":{"type":"string"},"data_b64":{"type":"string"}}}, "output": {"type":"object","properties": {"written":{"type":"integer"}}}})
+            }
+            PtyOp::Read => {
+                json!({"input": {"type":"object","required":["session_id"],"properties": {"session_id": {"type":"string"},"wait_ms":{"type":"integer"}}}, "output": {"type":"object","properties": {"data_b64":{"type":"string"},"dropped":{"type":"integer"},"redactions":{"type":"integer"},"exited":{"type":"boolean"},"exit_code":{"type":["integer","null"]},"signal":{"type":["integer","null"]}}}})
+            }
+            PtyOp::Resize => {
+                json!({"input": {"type":"object","required":["session_id","cols","rows"],"properties": {"session_id": {"type":"string"},"cols":{"type":"integer"},"rows":{"type":"integer"}}}, "output": {"type":"object","properties": {"cols":{"type":"integer"},"rows":{"type":"integer"}}}})
+            }
+            PtyOp::Close => {
+                json!({"input": {"type":"object","required":["session_id"],"properties": {"session_id": {"type":"string"}}}, "output": {"type":"object","properties": {"closed":{"type":"boolean"},"exit_code":{"type":["integer","null"]},"signal":{"type":["integer","null"]}}}})
+            }
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        match self.op {
+            PtyOp::Open => self.sessions.open(ctx, &params).await,
+            PtyOp::Write => self.sessions.write(ctx, &params).await,
+            PtyOp::Read => self.sessions.read(ctx, &params).await,
+            PtyOp::Resize => self.sessions.resize(ctx, &params).await,
+            PtyOp::Close => self.sessions.close(ctx, &params).await,
+        }
+    }
+}
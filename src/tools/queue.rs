@@ -0,0 +1,209 @@
+use crate::errors::AppError;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Bounds how many exec children run at once, globally and per principal.
+/// Waiters are served in arrival order, skipping those whose principal is
+/// already at its own limit so one busy principal can't stall everyone else.
+pub struct ExecQueue {
+    max_running: usize,
+    max_per_principal: usize,
+    max_queued: usize,
+    timeout: Duration,
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    running: usize,
+    per_principal: HashMap<String, usize>,
+    waiting: VecDeque<(u64, String)>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct QueueStats {
+    pub running: usize,
+    pub queued: usize,
+}
+
+impl QueueState {
+    fn has_capacity(&self, principal: &str, max_running: usize, max_per: usize) -> bool {
+        self.running < max_running
+            && self.per_principal.get(principal).copied().unwrap_or(0) < max_per
+    }
+
+    fn grant(&mut self, principal: &str) {
+        self.running += 1;
+        *self.per_principal.entry(principal.to_string()).or_default() += 1;
+    }
+}
+
+impl ExecQueue {
+    pub fn new(
+        max_running: usize,
+        max_per_principal: usize,
+        max_queued: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            max_running,
+            max_per_principal,
+            max_queued,
+            timeout,
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let st = self.state.lock().unwrap();
+        QueueStats {
+            running: st.running,
+            queued: st.waiting.len(),
+        }
+    }
+
+    /// Takes a free slot straight away when no waiter could use it first, and
+    /// otherwise joins the queue, failing immediately when it is full.
+    pub fn enqueue(self: &Arc<Self>, principal: &str) -> Result<Ticket, AppError> {
+        let mut st = self.state.lock().unwrap();
+        let (max_running, max_per) = (self.max_running, self.max_per_principal);
+        let granted = st.has_capacity(principal, max_running, max_per)
+            && !st
+                .waiting
+                .iter()
+                .any(|(_, p)| st.has_capacity(p, max_running, max_per));
+        if granted {
+            st.grant(principal);
+        } else if st.waiting.len() >= self.max_queued {
+            return Err(AppError::QueueFull);
+        }
+        let id = st.next_id;
+        st.next_id += 1;
+        if !granted {
+            st.waiting.push_back((id, principal.to_string()));
+        }
+        Ok(Ticket {
+            queue: self.clone(),
+            id,
+            principal: principal.to_string(),
+            granted,
+            done: false,
+        })
+    }
+
+    fn release(&self, principal: &str) {
+        let mut st = self.state.lock().unwrap();
+        st.running -= 1;
+        if let Some(n) = st.per_principal.get_mut(principal) {
+            *n -= 1;
+            if *n == 0 {
+                st.per_principal.remove(principal);
+            }
+        }
+        drop(st);
+        self.notify.notify_waiters();
+    }
+}
+
+/// A place in the queue, or a slot taken on arrival. Dropping it before
+/// `wait` returns leaves the queue or gives the slot back.
+pub struct Ticket {
+    queue: Arc<ExecQueue>,
+    id: u64,
+    principal: String,
+    granted: bool,
+    done: bool,
+}
+
+impl Ticket {
+    /// Waits for a slot, calling `on_position` with the 1-based queue position
+    /// whenever it changes.
+    pub async fn wait(
+        mut self,
+        mut on_position: impl FnMut(usize),
+    ) -> Result<ExecPermit, AppError> {
+        let queue = self.queue.clone();
+        if self.granted {
+            self.done = true;
+            return Ok(ExecPermit {
+                queue,
+                principal: self.principal.clone(),
+            });
+        }
+        let wait = async {
+            let mut last = 0;
+            loop {
+                let notified = queue.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                {
+                    let mut st = queue.state.lock().unwrap();
+                    let (max_running, max_per) = (queue.max_running, queue.max_per_principal);
+                    let first = st
+                        .waiting
+                        .iter()
+                        .position(|(_, p)| st.has_capacity(p, max_running, max_per));
+                    let mine = st.waiting.iter().position(|(id, _)| *id == self.id);
+                    if let (Some(f), Some(m)) = (first, mine) {
+                        if f == m {
+                            st.waiting.remove(m);
+                            st.grant(&self.principal);
+                            return;
+                        }
+                    }
+                    let position = mine.map(|m| m + 1).unwrap_or(1);
+                    if position != last {
+                        last = position;
+                        on_position(position);
+                    }
+                }
+                notified.await;
+            }
+        };
+        match tokio::time::timeout(queue.timeout, wait).await {
+            Ok(()) => {
+                self.done = true;
+                // a slot may still be free for the next waiter
+                queue.notify.notify_waiters();
+                Ok(ExecPermit {
+                    queue: queue.clone(),
+                    principal: self.principal.clone(),
+                })
+            }
+            Err(_) => Err(AppError::QueueTimeout),
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if self.granted {
+            self.queue.release(&self.principal);
+            return;
+        }
+        let mut st = self.queue.state.lock().unwrap();
+        st.waiting.retain(|(id, _)| *id != self.id);
+        drop(st);
+        self.queue.notify.notify_waiters();
+    }
+}
+
+/// A running slot; released when dropped.
+pub struct ExecPermit {
+    queue: Arc<ExecQueue>,
+    principal: String,
+}
+
+impl Drop for ExecPermit {
+    fn drop(&mut self) {
+        self.queue.release(&self.principal);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{config::SymlinkPolicy, errors::AppError};
+use std::collections::VecDeque;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+
+/// Symlinks expanded in one lookup before giving up, as with the kernel's ELOOP.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves tool paths one component at a time relative to an open root
+/// directory, never following a symlink the policy hasn't vetted. The final
+/// open or create happens relative to the parent directory fd with
+/// `O_NOFOLLOW`, so swapping a component for a symlink after the check fails
+/// instead of escaping root.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+}
+
+/// A path under root: the open parent directory plus what lies below it.
+#[derive(Debug)]
+pub struct Resolved {
+    parent: OwnedFd,
+    /// Directories that don't exist yet between `parent` and `name`.
+    missing: Vec<OsString>,
+    /// None for root itself.
+    name: Option<OsString>,
+    /// Root joined with the resolved components, symlinks expanded.
+    pub path: PathBuf,
+}
+
+/// What `lstat` says about a directory entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub kind: Kind,
+    pub size: u64,
+}
+
+impl Resolver {
+    pub fn new(root: &Path, symlinks: SymlinkPolicy) -> anyhow::Result<Self> {
+        Ok(Self {
+            root: dunce::canonicalize(root)?,
+            symlinks,
+        })
+    }
+
+    pub fn resolve(&self, input: &Path) -> Result<Resolved, AppError> {
+        let mut pending = self.components(input)?;
+        let root_fd = open_dir_at(libc::AT_FDCWD, self.root.as_os_str()).map_err(io_error)?;
+        // directories opened below root, innermost last
+        let mut stack: Vec<(OsString, OwnedFd)> = vec![];
+        let mut missing: Vec<OsString> = vec![];
+        let mut leaf: Option<OsString> = None;
+        let mut hops = 0;
+        while let Some(c) = pending.pop_front() {
+            if leaf.is_some() {
+                return Err(AppError::ToolError("not a directory".into()));
+            }
+            if c == ".." {
+                if missing.pop().is_none() && stack.pop().is_none() {
+                    return Err(AppError::PathOutsideRoot);
+                }
+                continue;
+            }
+            if !missing.is_empty() {
+                missing.push(c);
+                continue;
+            }
+            let dir = stack.last().map_or(&root_fd, |(_, fd)| fd).as_raw_fd();
+            let kind = match lstat_at(dir, &c) {
+                Ok(st) => st.kind,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    missing.push(c);
+                    continue;
+                }
+                Err(e) => return Err(io_error(e)),
+            };
+            match kind {
+                Kind::Dir => {
+                    let fd = open_dir_at(dir, &c).map_err(io_error)?;
+                    stack.push((c, fd));
+                }
+                Kind::Symlink => {
+                    if self.symlinks == SymlinkPolicy::Deny {
+                        return Err(AppError::SymlinkDenied);
+                    }
+                    hops += 1;
+                    if hops > MAX_SYMLINKS {
+                        return Err(AppError::ToolError(
+                            "too many levels of symbolic links".into(),
+                        ));
+                    }
+                    let target = read_link_at(dir, &c).map_err(io_error)?;
+                    if target.is_absolute() {
+                        stack.clear();
+                    }
+                    for t in self.components(&target)?.into_iter().rev() {
+                        pending.push_front(t);
+                    }
+                }
+                Kind::File | Kind::Other => leaf = Some(c),
+            }
+        }
+
+        let mut path = self.root.clone();
+        path.extend(stack.iter().map(|(n, _)| n));
+        path.extend(&missing);
+        path.extend(&leaf);
+        let name = match leaf {
+            Some(n) => Some(n),
+            None if !missing.is_empty() => missing.pop(),
+            None => stack.pop().map(|(n, _)| n),
+        };
+        let parent = stack.pop().map_or(root_fd, |(_, fd)| fd);
+        Ok(Resolved {
+            parent,
+            missing,
+            name,
+            path,
+        })
+    }
+
+    /// Components relative to root; `.` and the root prefix are dropped.
+    fn components(&self, input: &Path) -> Result<VecDeque<OsString>, AppError> {
+        let rel = if input.is_absolute() {
+            input
+                .strip_prefix(&self.root)
+                .map_err(|_| AppError::PathOutsideRoot)?
+        } else {
+            input
+        };
+        rel.components()
+            .filter_map(|c| match c {
+                Component::Normal(n) => Some(Ok(n.to_os_string())),
+                Component::ParentDir => Some(Ok(OsString::from(".."))),
+                Component::CurDir => None,
+                Component::RootDir | Component::Prefix(_) => Some(Err(AppError::PathOutsideRoot)),
+            })
+            .collect()
+    }
+}
+
+impl Resolved {
+    fn name(&self) -> &OsStr {
+        self.name.as_deref().unwrap_or(OsStr::new("."))
+    }
+
+    pub fn stat(&self) -> Result<Stat, AppError> {
+        if !self.missing.is_empty() {
+            return Err(AppError::NotFound);
+        }
+        lstat_at(self.parent.as_raw_fd(), self.name()).map_err(io_error)
+    }
+
+    pub fn exists(&self) -> bool {
+        self.stat().is_ok()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.stat().is_ok_and(|s| s.kind == Kind::Dir)
+    }
+
+    pub fn open_read(&self) -> Result<File, AppError> {
+        if !self.missing.is_empty() {
+            return Err(AppError::NotFound);
+        }
+        let fd =
+            open_at(self.parent.as_raw_fd(), self.name(), libc::O_RDONLY, 0).map_err(io_error)?;
+        Ok(File::from(fd))
+    }
+
+    /// Creates missing parent directories, then opens the file for writing,
+    /// truncating it or creating it with `mode` (before umask).
+    pub fn create(&self, mode: u32) -> Result<File, AppError> {
+        let Some(name) = &self.name else {
+            return Err(AppError::ToolError("is a directory".into()));
+        };
+        let mut dir: Option<OwnedFd> = None;
+        for m in &self.missing {
+            let at = dir.as_ref().unwrap_or(&self.parent).as_raw_fd();
+            let c = cstr(m)?;
+            if unsafe { libc::mkdirat(at, c.as_ptr(), 0o777) } != 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::AlreadyExists {
+                    return Err(io_error(e));
+                }
+            }
+            dir = Some(open_dir_at(at, m).map_err(io_error)?);
+        }
+        let at = dir.as_ref().unwrap_or(&self.parent).as_raw_fd();
+        let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+        let fd = open_at(at, name, flags, mode).map_err(io_error)?;
+        Ok(File::from(fd))
+    }
+
+    /// Entries of the directory, with `lstat` results; `.` and `..` skipped.
+    pub fn read_dir(&self) -> Result<Vec<(OsString, Stat)>, AppError> {
+        if !self.missing.is_empty() {
+            return Err(AppError::NotFound);
+        }
+        let fd = open_dir_at(self.parent.as_raw_fd(), self.name()).map_err(io_error)?;
+        let dirp = unsafe { libc::fdopendir(libc::dup(fd.as_raw_fd())) };
+        if dirp.is_null() {
+            return Err(io_error(io::Error::last_os_error()));
+        }
+        let mut names = vec![];
+        loop {
+            let ent = unsafe { libc::readdir(dirp) };
+            if ent.is_null() {
+                break;
+            }
+            let name = unsafe { std::ffi::CStr::from_ptr((*ent).d_name.as_ptr()) };
+            let name = OsStr::from_bytes(name.to_bytes());
+            if name != "." && name != ".." {
+                names.push(name.to_os_string());
+            }
+        }
+        unsafe { libc::closedir(dirp) };
+        Ok(names
+            .into_iter()
+            .filter_map(|n| {
+                let st = lstat_at(fd.as_raw_fd(), &n).ok()?;
+                Some((n, st))
+            })
+            .collect())
+    }
+}
+
+fn cstr(name: &OsStr) -> Result<CString, AppError> {
+    CString::new(name.as_bytes()).map_err(|_| AppError::ToolError("path contains NUL".into()))
+}
+
+fn open_at(dir: RawFd, name: &OsStr, flags: libc::c_int, mode: u32) -> io::Result<OwnedFd> {
+    let c = CString::new(name.as_bytes())?;
+    let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dir, c.as_ptr(), flags, mode as libc::c_uint) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn open_dir_at(dir: RawFd, name: &OsStr) -> io::Result<OwnedFd> {
+    open_at(dir, name, libc::O_RDONLY | libc::O_DIRECTORY, 0)
+}
+
+fn lstat_at(dir: RawFd, name: &OsStr) -> io::Result<Stat> {
+    let c = CString::new(name.as_bytes())?;
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatat(dir, c.as_ptr(), &mut st, libc::AT_SYMLINK_NOFOLLOW) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let kind = match st.st_mode & libc::S_IFMT {
+        libc::S_IFREG => Kind::File,
+        libc::S_IFDIR => Kind::Dir,
+        libc::S_IFLNK => Kind::Symlink,
+        _ => Kind::Other,
+    };
+    Ok(Stat {
+        kind,
+        size: st.st_size as u64,
+    })
+}
+
+fn read_link_at(dir: RawFd, name: &OsStr) -> io::Result<PathBuf> {
+    let c = CString::new(name.as_bytes())?;
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    let n = unsafe { libc::readlinkat(dir, c.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    buf.truncate(n as usize);
+    Ok(PathBuf::from(OsString::from_vec(buf)))
+}
+
+fn io_error(e: io::Error) -> AppError {
+    match e.raw_os_error() {
+        Some(libc::ENOENT) => AppError::NotFound,
+        // a component turned into a symlink after it was checked
+        Some(libc::ELOOP) => AppError::SymlinkDenied,
+        Some(libc::ENOTDIR) => AppError::ToolError("not a directory".into()),
+        Some(libc::EISDIR) => AppError::ToolError("is a directory".into()),
+        Some(libc::ENOTEMPTY) => AppError::ToolError("directory not empty".into()),
+        _ => AppError::Internal(e.to_string()),
+    }
+}
@@ -0,0 +1,698 @@
+use crate::config::{CommandPolicy, Exec, Isolation, Landlock, Rlimits};
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Confinement applied to one allowed command's children.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    namespaces: Option<Namespaces>,
+    landlock: Option<LandlockRules>,
+    seccomp: Option<Seccomp>,
+    rlimits: Rlimits,
+}
+
+impl Sandbox {
+    pub fn new(exec: &Exec, policy: Option<&CommandPolicy>, root: &Path) -> anyhow::Result<Self> {
+        let landlock = policy
+            .and_then(|p| p.landlock.as_ref())
+            .unwrap_or(&exec.landlock);
+        let isolation = policy
+            .and_then(|p| p.isolation.as_ref())
+            .unwrap_or(&exec.isolation);
+        let namespaces = Namespaces::new(isolation, root);
+        let mut landlock = LandlockRules::new(landlock, root);
+        if let (Some(rules), Some(_)) = (&mut landlock, &namespaces) {
+            // rules on a directory don't reach a filesystem mounted over it,
+            // so the child's fresh /tmp and /proc get their own
+            rules.remounted.push((c"/tmp".into(), true));
+            if rules.read.iter().any(|p| p == Path::new("/proc")) {
+                rules.remounted.push((c"/proc".into(), false));
+            }
+        }
+        let profile = policy
+            .and_then(|p| p.seccomp.as_deref())
+            .unwrap_or(&exec.seccomp);
+        Ok(Self {
+            namespaces,
+            landlock,
+            seccomp: Seccomp::new(exec, profile)?,
+            rlimits: *policy
+                .and_then(|p| p.rlimits.as_ref())
+                .unwrap_or(&exec.rlimits),
+        })
+    }
+
+    /// Arranges for the child to confine itself between fork and exec.
+    /// Namespaces come first: a Landlock-restricted process can't mount, and
+    /// the seccomp filter goes last as it may block the calls before it.
+    pub fn apply(&self, command: &mut Command) -> io::Result<()> {
+        if let Some(ns) = &self.namespaces {
+            ns.apply(command);
+        }
+        if let Some(rules) = &self.landlock {
+            rules.apply(command)?;
+        }
+        self.apply_rlimits(command);
+        if let Some(seccomp) = &self.seccomp {
+            seccomp.apply(command);
+        }
+        Ok(())
+    }
+
+    pub fn rlimits(&self) -> &Rlimits {
+        &self.rlimits
+    }
+
+    fn apply_rlimits(&self, command: &mut Command) {
+        use std::os::unix::process::CommandExt;
+        const MB: u64 = 1024 * 1024;
+        let r = &self.rlimits;
+        // the hard CPU limit a second past the soft one kills a process
+        // that ignores SIGXCPU
+        let limits: Vec<_> = [
+            (libc::RLIMIT_CPU, r.cpu_s.map(|s| (s, s + 1))),
+            (libc::RLIMIT_AS, r.as_mb.map(|m| (m * MB, m * MB))),
+            (libc::RLIMIT_FSIZE, r.fsize_mb.map(|m| (m * MB, m * MB))),
+            (libc::RLIMIT_NOFILE, r.nofile.map(|n| (n, n))),
+            (libc::RLIMIT_NPROC, r.nproc.map(|n| (n, n))),
+        ]
+        .into_iter()
+        .filter_map(|(res, v)| v.map(|(soft, hard)| (res, soft, hard)))
+        .collect();
+        if limits.is_empty() {
+            return;
+        }
+        unsafe {
+            command.pre_exec(move || {
+                for &(res, soft, hard) in &limits {
+                    let rl = libc::rlimit {
+                        rlim_cur: soft as libc::rlim_t,
+                        rlim_max: hard as libc::rlim_t,
+                    };
+                    cvt(libc::setrlimit(res, &rl))?;
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Paths an exec child may touch. Everything else is denied, including paths
+/// created later.
+#[derive(Debug, Clone)]
+struct LandlockRules {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+    /// Mount points replaced inside the child, with whether they're writable.
+    remounted: Vec<(CString, bool)>,
+}
+
+impl LandlockRules {
+    fn new(cfg: &Landlock, root: &Path) -> Option<Self> {
+        if !cfg.enabled {
+            return None;
+        }
+        let mut read = cfg.read.clone();
+        read.push(root.to_path_buf());
+        let mut write = cfg.write.clone();
+        write.extend(cfg.root_write.iter().map(|p| root.join(p)));
+        Some(Self {
+            read,
+            write,
+            remounted: vec![],
+        })
+    }
+
+    /// The ruleset is built (and its path fds opened) before fork; the child
+    /// only adds rules for remounted paths and calls `restrict_self`. Missing
+    /// paths are skipped, and kernels without Landlock run the command
+    /// unconfined.
+    #[cfg(target_os = "linux")]
+    fn apply(&self, command: &mut Command) -> io::Result<()> {
+        use landlock::{
+            path_beneath_rules, Access, AccessFs, PathBeneath, Ruleset, RulesetAttr,
+            RulesetCreatedAttr, ABI,
+        };
+        use std::os::fd::{FromRawFd, OwnedFd};
+        use std::os::unix::process::CommandExt;
+
+        let abi = ABI::V3;
+        let ruleset = Ruleset::default()
+            .handle_access(AccessFs::from_all(abi))
+            .and_then(|r| r.create())
+            .and_then(|r| r.add_rules(path_beneath_rules(&self.read, AccessFs::from_read(abi))))
+            .and_then(|r| r.add_rules(path_beneath_rules(&self.write, AccessFs::from_all(abi))))
+            .map_err(io::Error::other)?;
+        let mut ruleset = Some(ruleset);
+        let remounted = self.remounted.clone();
+        unsafe {
+            command.pre_exec(move || {
+                let Some(mut r) = ruleset.take() else {
+                    return Ok(());
+                };
+                for (path, writable) in &remounted {
+                    let fd = libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        continue;
+                    }
+                    let access = if *writable {
+                        AccessFs::from_all(abi)
+                    } else {
+                        AccessFs::from_read(abi)
+                    };
+                    let rule = PathBeneath::new(OwnedFd::from_raw_fd(fd), access);
+                    r = r.add_rule(rule).map_err(io::Error::other)?;
+                }
+                r.restrict_self().map_err(io::Error::other)?;
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn apply(&self, _command: &mut Command) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Built-in profile: debugging other processes, kernel and mount
+/// administration, and interfaces with a history of privilege escalation.
+const DEFAULT_DENY: &[&str] = &[
+    "ptrace",
+    "process_vm_readv",
+    "process_vm_writev",
+    "mount",
+    "umount2",
+    "pivot_root",
+    "setns",
+    "kexec_load",
+    "kexec_file_load",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "reboot",
+    "swapon",
+    "swapoff",
+    "acct",
+    "quotactl",
+    "bpf",
+    "perf_event_open",
+    "userfaultfd",
+    "keyctl",
+    "add_key",
+    "request_key",
+    "open_by_handle_at",
+    "fanotify_init",
+    "io_uring_setup",
+    "settimeofday",
+    "clock_settime",
+    "clock_adjtime",
+    "adjtimex",
+    "sethostname",
+    "setdomainname",
+    "syslog",
+    "iopl",
+    "ioperm",
+    "modify_ldt",
+    "uselib",
+];
+
+/// A compiled seccomp-bpf filter. Denied syscalls kill the whole process
+/// with SIGSYS, which exec reports as `termination: "seccomp"`.
+#[derive(Debug, Clone)]
+struct Seccomp {
+    #[cfg(target_os = "linux")]
+    program: std::sync::Arc<seccompiler::BpfProgram>,
+}
+
+impl Seccomp {
+    fn new(exec: &Exec, profile: &str) -> anyhow::Result<Option<Self>> {
+        let names: Vec<&str> = match profile {
+            "none" => return Ok(None),
+            "default" => DEFAULT_DENY.to_vec(),
+            name => {
+                let Some(p) = exec.seccomp_profiles.get(name) else {
+                    anyhow::bail!("unknown seccomp profile: {name}");
+                };
+                let base = if p.base == "default" {
+                    DEFAULT_DENY
+                } else {
+                    &[]
+                };
+                base.iter()
+                    .copied()
+                    .chain(p.deny.iter().map(String::as_str))
+                    .collect()
+            }
+        };
+        Self::compile(profile, &names)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn compile(profile: &str, names: &[&str]) -> anyhow::Result<Option<Self>> {
+        use seccompiler::{SeccompAction, SeccompFilter, TargetArch};
+        let mut rules = std::collections::BTreeMap::new();
+        for name in names {
+            match syscall_number(name) {
+                Some(nr) => {
+                    rules.insert(nr, vec![]);
+                }
+                // the built-in list names a few syscalls only some
+                // architectures have
+                None if DEFAULT_DENY.contains(name) => {}
+                None => anyhow::bail!("seccomp profile {profile}: unknown syscall {name}"),
+            }
+        }
+        let arch = TargetArch::try_from(std::env::consts::ARCH)?;
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::KillProcess,
+            arch,
+        )?;
+        Ok(Some(Self {
+            program: std::sync::Arc::new(filter.try_into()?),
+        }))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn compile(_profile: &str, _names: &[&str]) -> anyhow::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn apply(&self, command: &mut Command) {
+        use std::os::unix::process::CommandExt;
+        let program = self.program.clone();
+        unsafe {
+            command.pre_exec(move || seccompiler::apply_filter(&program).map_err(io::Error::other));
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn apply(&self, _command: &mut Command) {}
+}
+
+/// Syscall numbers for the names a profile may deny. Names this
+/// architecture doesn't have resolve to None.
+#[cfg(target_os = "linux")]
+fn syscall_number(name: &str) -> Option<i64> {
+    let nr = match name {
+        "accept" => libc::SYS_accept,
+        "accept4" => libc::SYS_accept4,
+        "acct" => libc::SYS_acct,
+        "add_key" => libc::SYS_add_key,
+        "adjtimex" => libc::SYS_adjtimex,
+        "bind" => libc::SYS_bind,
+        "bpf" => libc::SYS_bpf,
+        "chroot" => libc::SYS_chroot,
+        "clock_adjtime" => libc::SYS_clock_adjtime,
+        "clock_settime" => libc::SYS_clock_settime,
+        "clone" => libc::SYS_clone,
+        "clone3" => libc::SYS_clone3,
+        "connect" => libc::SYS_connect,
+        "delete_module" => libc::SYS_delete_module,
+        "execve" => libc::SYS_execve,
+        "execveat" => libc::SYS_execveat,
+        "fanotify_init" => libc::SYS_fanotify_init,
+        "finit_module" => libc::SYS_finit_module,
+        "flock" => libc::SYS_flock,
+        "getpid" => libc::SYS_getpid,
+        "getppid" => libc::SYS_getppid,
+        "init_module" => libc::SYS_init_module,
+        "io_uring_enter" => libc::SYS_io_uring_enter,
+        "io_uring_register" => libc::SYS_io_uring_register,
+        "io_uring_setup" => libc::SYS_io_uring_setup,
+        "ioctl" => libc::SYS_ioctl,
+        "kcmp" => libc::SYS_kcmp,
+        "kexec_file_load" => libc::SYS_kexec_file_load,
+        "kexec_load" => libc::SYS_kexec_load,
+        "keyctl" => libc::SYS_keyctl,
+        "kill" => libc::SYS_kill,
+        "listen" => libc::SYS_listen,
+        "memfd_create" => libc::SYS_memfd_create,
+        "mknodat" => libc::SYS_mknodat,
+        "mount" => libc::SYS_mount,
+        "move_mount" => libc::SYS_move_mount,
+        "name_to_handle_at" => libc::SYS_name_to_handle_at,
+        "open_by_handle_at" => libc::SYS_open_by_handle_at,
+        "open_tree" => libc::SYS_open_tree,
+        "perf_event_open" => libc::SYS_perf_event_open,
+        "personality" => libc::SYS_personality,
+        "pidfd_getfd" => libc::SYS_pidfd_getfd,
+        "pidfd_open" => libc::SYS_pidfd_open,
+        "pivot_root" => libc::SYS_pivot_root,
+        "prctl" => libc::SYS_prctl,
+        "process_vm_readv" => libc::SYS_process_vm_readv,
+        "process_vm_writev" => libc::SYS_process_vm_writev,
+        "ptrace" => libc::SYS_ptrace,
+        "quotactl" => libc::SYS_quotactl,
+        "reboot" => libc::SYS_reboot,
+        "recvfrom" => libc::SYS_recvfrom,
+        "recvmsg" => libc::SYS_recvmsg,
+        "request_key" => libc::SYS_request_key,
+        "sendmsg" => libc::SYS_sendmsg,
+        "sendto" => libc::SYS_sendto,
+        "setdomainname" => libc::SYS_setdomainname,
+        "setfsgid" => libc::SYS_setfsgid,
+        "setfsuid" => libc::SYS_setfsuid,
+        "setgid" => libc::SYS_setgid,
+        "sethostname" => libc::SYS_sethostname,
+        "setns" => libc::SYS_setns,
+        "setpriority" => libc::SYS_setpriority,
+        "setresgid" => libc::SYS_setresgid,
+        "setresuid" => libc::SYS_setresuid,
+        "setrlimit" => libc::SYS_setrlimit,
+        "settimeofday" => libc::SYS_settimeofday,
+        "setuid" => libc::SYS_setuid,
+        "socket" => libc::SYS_socket,
+        "socketpair" => libc::SYS_socketpair,
+        "splice" => libc::SYS_splice,
+        "swapoff" => libc::SYS_swapoff,
+        "swapon" => libc::SYS_swapon,
+        "syslog" => libc::SYS_syslog,
+        "tee" => libc::SYS_tee,
+        "tgkill" => libc::SYS_tgkill,
+        "tkill" => libc::SYS_tkill,
+        "umount2" => libc::SYS_umount2,
+        "unshare" => libc::SYS_unshare,
+        "userfaultfd" => libc::SYS_userfaultfd,
+        "vhangup" => libc::SYS_vhangup,
+        "vmsplice" => libc::SYS_vmsplice,
+        _ => return arch_syscall_number(name),
+    };
+    Some(nr)
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn arch_syscall_number(name: &str) -> Option<i64> {
+    let nr = match name {
+        "chmod" => libc::SYS_chmod,
+        "chown" => libc::SYS_chown,
+        "creat" => libc::SYS_creat,
+        "fork" => libc::SYS_fork,
+        "ioperm" => libc::SYS_ioperm,
+        "iopl" => libc::SYS_iopl,
+        "lchown" => libc::SYS_lchown,
+        "link" => libc::SYS_link,
+        "mknod" => libc::SYS_mknod,
+        "modify_ldt" => libc::SYS_modify_ldt,
+        "open" => libc::SYS_open,
+        "rename" => libc::SYS_rename,
+        "symlink" => libc::SYS_symlink,
+        "unlink" => libc::SYS_unlink,
+        "uselib" => libc::SYS_uselib,
+        "vfork" => libc::SYS_vfork,
+        _ => return None,
+    };
+    Some(nr)
+}
+
+#[cfg(all(target_os = "linux", not(target_arch = "x86_64")))]
+fn arch_syscall_number(_name: &str) -> Option<i64> {
+    None
+}
+
+/// Whether a seccomp profile may name `name`, for config validation. Off
+/// Linux there is no filter, so any name passes.
+pub(crate) fn known_syscall(name: &str) -> bool {
+    #[cfg(target_os = "linux")]
+    return syscall_number(name).is_some() || DEFAULT_DENY.contains(&name);
+    #[cfg(not(target_os = "linux"))]
+    return !name.is_empty();
+}
+
+/// Everything the child needs to enter its namespaces, prepared before fork
+/// so the child only makes raw syscalls.
+#[derive(Debug, Clone)]
+struct Namespaces {
+    network: bool,
+    root: CString,
+    /// root_dir lies under /tmp and must be mounted again over the tmpfs;
+    /// these are its ancestors below /tmp, outermost first.
+    rebind: Option<Vec<CString>>,
+    tmp_opts: CString,
+    uid_map: CString,
+    gid_map: CString,
+}
+
+impl Namespaces {
+    fn new(cfg: &Isolation, root: &Path) -> Option<Self> {
+        if !cfg.enabled {
+            return None;
+        }
+        let cstr = |p: &Path| CString::new(p.as_os_str().as_bytes()).ok();
+        let rebind = root.strip_prefix("/tmp").ok().map(|rel| {
+            let mut dir = PathBuf::from("/tmp");
+            rel.components()
+                .filter_map(|c| {
+                    dir.push(c);
+                    cstr(&dir)
+                })
+                .collect()
+        });
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Some(Self {
+            network: cfg.network,
+            root: cstr(root)?,
+            rebind,
+            tmp_opts: CString::new(format!("size={}m,mode=1777", cfg.tmp_size_mb)).ok()?,
+            uid_map: CString::new(format!("{uid} {uid} 1")).ok()?,
+            gid_map: CString::new(format!("{gid} {gid} 1")).ok()?,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn apply(&self, command: &mut Command) {
+        use std::os::unix::process::CommandExt;
+        let ns = self.clone();
+        unsafe {
+            command.pre_exec(move || ns.enter());
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn apply(&self, _command: &mut Command) {}
+
+    /// Runs in the forked child. The PID namespace only applies to children,
+    /// so this forks once more: the command runs as PID 1 of the new
+    /// namespace while the first child waits and passes on its exit status.
+    #[cfg(target_os = "linux")]
+    fn enter(&self) -> io::Result<()> {
+        use libc::{
+            CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID, CLONE_NEWUSER, MS_BIND,
+            MS_NODEV, MS_NOEXEC, MS_NOSUID, MS_PRIVATE, MS_RDONLY, MS_REC,
+        };
+        let mut flags = CLONE_NEWUSER | CLONE_NEWNS | CLONE_NEWPID | CLONE_NEWIPC;
+        if !self.network {
+            flags |= CLONE_NEWNET;
+        }
+        // the working directory std set up, somewhere under root_dir
+        let mut cwd = [0u8; libc::PATH_MAX as usize];
+        unsafe {
+            if libc::getcwd(cwd.as_mut_ptr().cast(), cwd.len()).is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            cvt(libc::unshare(flags))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", self.uid_map.as_bytes())?;
+            write_file(c"/proc/self/gid_map", self.gid_map.as_bytes())?;
+            mount(None, c"/", None, MS_REC | MS_PRIVATE, None)?;
+            // root_dir becomes its own mount so it can stay writable
+            mount(Some(&self.root), &self.root, None, MS_BIND | MS_REC, None)?;
+            set_readonly(c"/", true)?;
+            set_readonly(&self.root, false)?;
+            let root_fd = cvt(libc::open(
+                self.root.as_ptr(),
+                libc::O_PATH | libc::O_CLOEXEC,
+            ))?;
+            mount(
+                Some(c"tmpfs"),
+                c"/tmp",
+                Some(c"tmpfs"),
+                MS_NOSUID | MS_NODEV,
+                Some(&self.tmp_opts),
+            )?;
+            if let Some(dirs) = &self.rebind {
+                for d in dirs {
+                    if libc::mkdir(d.as_ptr(), 0o755) != 0
+                        && io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST)
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                let mut buf = [0u8; 32];
+                mount(
+                    Some(fd_path(&mut buf, root_fd)),
+                    &self.root,
+                    None,
+                    MS_BIND | MS_REC,
+                    None,
+                )?;
+            }
+            libc::close(root_fd);
+            // the inherited working directory still points into the old mounts
+            cvt(libc::chdir(cwd.as_ptr().cast()))?;
+            if !self.network {
+                loopback_up();
+            }
+            let pid = cvt(libc::fork())?;
+            if pid > 0 {
+                relay(pid);
+            }
+            // killing the first child takes the whole namespace down with it
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+            // a fresh /proc shows only this namespace; where that isn't
+            // allowed the host's is hidden instead
+            let proc_flags = MS_NOSUID | MS_NODEV | MS_NOEXEC;
+            if mount(Some(c"proc"), c"/proc", Some(c"proc"), proc_flags, None).is_err() {
+                mount(
+                    Some(c"tmpfs"),
+                    c"/proc",
+                    Some(c"tmpfs"),
+                    proc_flags | MS_RDONLY,
+                    None,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn cvt(r: libc::c_int) -> io::Result<libc::c_int> {
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(r)
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn write_file(path: &std::ffi::CStr, data: &[u8]) -> io::Result<()> {
+    let fd = cvt(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+    let n = libc::write(fd, data.as_ptr().cast(), data.len());
+    libc::close(fd);
+    if n != data.len() as isize {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn mount(
+    src: Option<&std::ffi::CStr>,
+    target: &std::ffi::CStr,
+    fstype: Option<&std::ffi::CStr>,
+    flags: libc::c_ulong,
+    data: Option<&std::ffi::CStr>,
+) -> io::Result<()> {
+    let ptr = |c: Option<&std::ffi::CStr>| c.map_or(std::ptr::null(), |c| c.as_ptr());
+    cvt(libc::mount(
+        ptr(src),
+        target.as_ptr(),
+        ptr(fstype),
+        flags,
+        ptr(data).cast(),
+    ))?;
+    Ok(())
+}
+
+/// Sets or clears read-only on a mount and everything below it (Linux 5.12+).
+#[cfg(target_os = "linux")]
+unsafe fn set_readonly(path: &std::ffi::CStr, readonly: bool) -> io::Result<()> {
+    #[repr(C)]
+    struct MountAttr {
+        attr_set: u64,
+        attr_clr: u64,
+        propagation: u64,
+        userns_fd: u64,
+    }
+    const MOUNT_ATTR_RDONLY: u64 = 0x1;
+    const AT_RECURSIVE: libc::c_int = 0x8000;
+    let (set, clr) = if readonly {
+        (MOUNT_ATTR_RDONLY, 0)
+    } else {
+        (0, MOUNT_ATTR_RDONLY)
+    };
+    let attr = MountAttr {
+        attr_set: set,
+        attr_clr: clr,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let r = libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path.as_ptr(),
+        AT_RECURSIVE,
+        &attr as *const MountAttr,
+        std::mem::size_of::<MountAttr>(),
+    );
+    cvt(r as libc::c_int)?;
+    Ok(())
+}
+
+/// `/proc/self/fd/<fd>` written into `buf` without allocating.
+#[cfg(target_os = "linux")]
+fn fd_path(buf: &mut [u8; 32], fd: libc::c_int) -> &std::ffi::CStr {
+    let prefix = b"/proc/self/fd/";
+    buf[..prefix.len()].copy_from_slice(prefix);
+    let mut digits = [0u8; 10];
+    let (mut n, mut len) = (fd as u32, 0);
+    loop {
+        digits[len] = b'0' + (n % 10) as u8;
+        len += 1;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    for i in 0..len {
+        buf[prefix.len() + i] = digits[len - 1 - i];
+    }
+    buf[prefix.len() + len] = 0;
+    std::ffi::CStr::from_bytes_until_nul(buf).unwrap_or(c"/")
+}
+
+/// Brings up `lo` in a fresh network namespace so local test servers work.
+#[cfg(target_os = "linux")]
+unsafe fn loopback_up() {
+    let sock = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if sock < 0 {
+        return;
+    }
+    let mut req: libc::ifreq = std::mem::zeroed();
+    req.ifr_name[0] = b'l' as libc::c_char;
+    req.ifr_name[1] = b'o' as libc::c_char;
+    if libc::ioctl(sock, libc::SIOCGIFFLAGS, &mut req) == 0 {
+        req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        libc::ioctl(sock, libc::SIOCSIFFLAGS, &req);
+    }
+    libc::close(sock);
+}
+
+/// Waits for the namespaced command and exits the same way it did.
+#[cfg(target_os = "linux")]
+unsafe fn relay(pid: libc::pid_t) -> ! {
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) != pid {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(127);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let sig = libc::WTERMSIG(status);
+        libc::signal(sig, libc::SIG_DFL);
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, sig);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::kill(libc::getpid(), sig);
+        libc::_exit(128 + sig);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
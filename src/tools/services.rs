@@ -0,0 +1,573 @@
+use crate::{
+    budget::Usage,
+    config::{self, Config, Restart},
+    errors::AppError,
+    mcp::registry::{CallContext, Tool},
+    redact::Redactor,
+    tools::exec::{termination, ExecTool, JobChild},
+};
+use async_trait::async_trait;
+use base64::Engine;
+use regex::bytes::Regex;
+use serde_json::json;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+use tokio::sync::Notify;
+
+/// Largest page `service_logs` returns, and how much it returns from the
+/// end of the log when no offset is given.
+const MAX_PAGE: usize = 64 * 1024;
+/// How often a `ready_port` probe tries to connect.
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+/// Longest partial line kept for `ready_log` matching.
+const MAX_LINE: usize = 64 * 1024;
+
+/// The services from `[services]`, shared by the `service_*` tools. A
+/// started service has a task that owns its child, keeps its redacted log,
+/// probes readiness and restarts it according to its `restart` policy.
+pub struct Services {
+    exec: Arc<ExecTool>,
+    redactor: Arc<Redactor>,
+    kill_grace: Duration,
+    services: BTreeMap<String, Arc<Service>>,
+}
+
+struct Service {
+    name: String,
+    cfg: config::Service,
+    ready_log: Option<Regex>,
+    state: Mutex<State>,
+    /// Signalled whenever the status changes.
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    status: Status,
+    /// Set by `service_start`, taken by `service_stop`; each start gets its
+    /// own so a stop can't leak into the next run.
+    stop: Option<Arc<Notify>>,
+    started_by: Option<String>,
+    started_at: Option<u64>,
+    restarts: u32,
+    last_exit: Option<LastExit>,
+    log: VecDeque<u8>,
+    /// Bytes ever logged; `service_logs` offsets count from the first run.
+    logged: u64,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Status {
+    #[default]
+    Stopped,
+    Starting,
+    Ready,
+    Stopping,
+    Failed,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Stopped => "stopped",
+            Status::Starting => "starting",
+            Status::Ready => "ready",
+            Status::Stopping => "stopping",
+            Status::Failed => "failed",
+        }
+    }
+
+    fn running(self) -> bool {
+        matches!(self, Status::Starting | Status::Ready | Status::Stopping)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LastExit {
+    exit_code: Option<i32>,
+    termination: &'static str,
+    signal: Option<i32>,
+}
+
+impl Services {
+    pub fn new(cfg: &Config, exec: Arc<ExecTool>) -> anyhow::Result<Self> {
+        let mut services = BTreeMap::new();
+        for (name, svc) in &cfg.services {
+            exec.check_allowed(&svc.cmd)
+                .map_err(|e| anyhow::anyhow!("[services.{name}] {e}"))?;
+            let service = Service {
+                name: name.clone(),
+                cfg: svc.clone(),
+                ready_log: svc.ready_log.as_deref().map(Regex::new).transpose()?,
+                state: Mutex::default(),
+                changed: Notify::new(),
+            };
+            services.insert(name.clone(), Arc::new(service));
+        }
+        Ok(Self {
+            exec,
+            redactor: Arc::new(Redactor::new(cfg)?),
+            kill_grace: Duration::from_millis(cfg.limits.exec_kill_grace_ms),
+            services,
+        })
+    }
+
+    fn get(&self, params: &serde_json::Value) -> Result<&Arc<Service>, AppError> {
+        let name = params
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| AppError::ToolError("missing name".into()))?;
+        self.services.get(name).ok_or(AppError::NotFound)
+    }
+
+    /// Like [`Self::get`], but only for the principal that last started the
+    /// service: anyone may start or inspect a service, but only its starter may
+    /// stop it or read its logs.
+    fn owned(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<&Arc<Service>, AppError> {
+        let service = self.get(params)?;
+        let state = service.state.lock().unwrap();
+        match &state.started_by {
+            Some(owner) if *owner != ctx.principal.name => Err(AppError::ScopeDenied),
+            _ => Ok(service),
+        }
+    }
+
+    async fn start(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let service = self.get(params)?;
+        let stop = Arc::new(Notify::new());
+        let fresh = {
+            let mut state = service.state.lock().unwrap();
+            match state.status {
+                Status::Stopping => return Err(AppError::ToolError("service is stopping".into())),
+                Status::Starting | Status::Ready => false,
+                Status::Stopped | Status::Failed => {
+                    state.status = Status::Starting;
+                    state.stop = Some(stop.clone());
+                    state.started_by = Some(ctx.principal.name.clone());
+                    state.restarts = 0;
+                    true
+                }
+            }
+        };
+        // already running: report it rather than start a second copy
+        if fresh {
+            let child = match self
+                .exec
+                .spawn_service(ctx, &service.params(), &service.cfg.env)
+            {
+                Ok(c) => c,
+                Err(e) => {
+                    service.set(|s| {
+                        s.status = Status::Stopped;
+                        s.stop = None;
+                    });
+                    return Err(e);
+                }
+            };
+            tracing::info!(service = %service.name, principal = %ctx.principal.name, "service started");
+            tokio::spawn(service.clone().supervise(
+                child,
+                stop,
+                ctx.clone(),
+                self.exec.clone(),
+                self.redactor.clone(),
+                self.kill_grace,
+            ));
+        }
+        if params.get("wait").and_then(|v| v.as_bool()) != Some(false) {
+            let limit = Duration::from_secs(service.cfg.ready_timeout_s);
+            service.wait_while(limit, |s| s == Status::Starting).await;
+        }
+        Ok(service.status())
+    }
+
+    async fn stop(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let service = self.owned(ctx, params)?;
+        self.stop_service(service).await;
+        Ok(service.status())
+    }
+
+    async fn stop_service(&self, service: &Service) {
+        let stop = {
+            let mut state = service.state.lock().unwrap();
+            if state.status.running() {
+                state.status = Status::Stopping;
+            }
+            state.stop.take()
+        };
+        if let Some(stop) = stop {
+            stop.notify_one();
+        }
+        let limit = self.kill_grace + Duration::from_secs(1);
+        service.wait_while(limit, Status::running).await;
+    }
+
+    /// Stops every running service, for when Valet shuts down.
+    pub async fn stop_all(&self) {
+        let stops = self.services.values().map(|s| self.stop_service(s));
+        futures::future::join_all(stops).await;
+    }
+
+    async fn status(
+        &self,
+        _ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        if params.get("name").is_some() {
+            return Ok(self.get(params)?.status());
+        }
+        let all: Vec<_> = self.services.values().map(|s| s.status()).collect();
+        Ok(json!({"services": all}))
+    }
+
+    async fn logs(
+        &self,
+        ctx: &CallContext,
+        params: &serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        let service = self.owned(ctx, params)?;
+        let limit = params
+            .get("limit")
+            .and_then(|v| v.as_u64())
+            .map_or(MAX_PAGE, |l| (l as usize).min(MAX_PAGE));
+        let state = service.state.lock().unwrap();
+        let first = state.logged - state.log.len() as u64;
+        let offset = params
+            .get("offset")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(state.logged.saturating_sub(limit as u64));
+        let start = offset.clamp(first, state.logged);
+        let from = (start - first) as usize;
+        let to = (from + limit).min(state.log.len());
+        let data: Vec<u8> = state.log.range(from..to).copied().collect();
+        Ok(json!({
+            "data_b64": base64::engine::general_purpose::STANDARD.encode(&data),
+            "offset": start,
+            "next_offset": start + data.len() as u64,
+            "total": state.logged,
+            "dropped": first.saturating_sub(offset),
+        }))
+    }
+}
+
+impl Service {
+    /// Arguments for [`ExecTool::spawn_service`], checked like an exec call.
+    fn params(&self) -> serde_json::Value {
+        json!({"cmd": self.cfg.cmd, "args": self.cfg.args, "cwd": self.cfg.cwd})
+    }
+
+    fn set(&self, f: impl FnOnce(&mut State)) {
+        f(&mut self.state.lock().unwrap());
+        self.changed.notify_waiters();
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let last = state.last_exit;
+        json!({
+            "name": self.name,
+            "status": state.status.as_str(),
+            "ready": state.status == Status::Ready,
+            "started_by": state.started_by,
+            "started_at": state.started_at,
+            "restarts": state.restarts,
+            "exit_code": last.and_then(|e| e.exit_code),
+            "termination": last.map(|e| e.termination),
+            "signal": last.and_then(|e| e.signal),
+            "log_bytes": state.logged,
+        })
+    }
+
+    /// Waits up to `limit` for the status to stop satisfying `pending`.
+    async fn wait_while(&self, limit: Duration, pending: impl Fn(Status) -> bool) {
+        let deadline = tokio::time::Instant::now() + limit;
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if !pending(self.state.lock().unwrap().status) {
+                return;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn log(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.log.extend(data);
+        state.logged += data.len() as u64;
+        let excess = state.log.len().saturating_sub(self.cfg.log_kb * 1024);
+        state.log.drain(..excess);
+    }
+
+    fn mark_ready(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.status == Status::Starting {
+            state.status = Status::Ready;
+            drop(state);
+            tracing::info!(service = %self.name, "service ready");
+            self.changed.notify_waiters();
+        }
+    }
+
+    /// Runs the service until it is stopped or its restart policy gives up.
+    async fn supervise(
+        self: Arc<Self>,
+        mut child: JobChild,
+        stop: Arc<Notify>,
+        ctx: CallContext,
+        exec: Arc<ExecTool>,
+        redactor: Arc<Redactor>,
+        kill_grace: Duration,
+    ) {
+        loop {
+            let started_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            self.set(|s| s.started_at = Some(started_at));
+            let (last, stopped) = self.run(child, &stop, &ctx, &redactor, kill_grace).await;
+            let failed = last.termination != "exit" || last.exit_code != Some(0);
+            let restart = match self.cfg.restart {
+                Restart::Never => false,
+                Restart::OnFailure => failed,
+                Restart::Always => true,
+            };
+            let again = {
+                let mut state = self.state.lock().unwrap();
+                state.last_exit = Some(last);
+                let stopped = stopped || state.status == Status::Stopping;
+                if stopped || !restart || state.restarts >= self.cfg.max_restarts {
+                    state.status = if failed && !stopped {
+                        Status::Failed
+                    } else {
+                        Status::Stopped
+                    };
+                    state.stop = None;
+                    false
+                } else {
+                    state.restarts += 1;
+                    state.status = Status::Starting;
+                    true
+                }
+            };
+            self.changed.notify_waiters();
+            if !again {
+                tracing::info!(service = %self.name, exit_code = ?last.exit_code, termination = last.termination, "service ended");
+                return;
+            }
+            tracing::warn!(service = %self.name, exit_code = ?last.exit_code, termination = last.termination, "service exited, restarting");
+            let delay = Duration::from_millis(self.cfg.restart_delay_ms);
+            let stopped = tokio::select! {
+                _ = tokio::time::sleep(delay) => false,
+                _ = stop.notified() => true,
+            };
+            let spawned = if stopped {
+                None
+            } else {
+                match exec.spawn_service(&ctx, &self.params(), &self.cfg.env) {
+                    Ok(c) => Some(c),
+                    Err(e) => {
+                        tracing::warn!(service = %self.name, error = %e, "service restart failed");
+                        None
+                    }
+                }
+            };
+            match spawned {
+                Some(c) => child = c,
+                None => {
+                    self.set(|s| {
+                        s.status = if stopped {
+                            Status::Stopped
+                        } else {
+                            Status::Failed
+                        };
+                        s.stop = None;
+                    });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Runs one child to its end, logging its output and probing readiness.
+    /// Returns how it ended and whether `service_stop` ended it.
+    async fn run(
+        &self,
+        spawned: JobChild,
+        stop: &Notify,
+        ctx: &CallContext,
+        redactor: &Arc<Redactor>,
+        kill_grace: Duration,
+    ) -> (LastExit, bool) {
+        let JobChild {
+            mut child,
+            feeder,
+            rlimits,
+            ..
+        } = spawned;
+        let start = Instant::now();
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let mut out_redactor = redactor.stream();
+        let mut err_redactor = redactor.stream();
+        let (mut buf_out, mut buf_err) = ([0u8; 8192], [0u8; 8192]);
+        let (mut out_done, mut err_done) = (false, false);
+        let (mut out_line, mut err_line) = (Vec::new(), Vec::new());
+        let probe = self.cfg.ready_port;
+        if probe.is_none() && self.ready_log.is_none() {
+            self.mark_ready();
+        }
+        let mut tick = tokio::time::interval(PROBE_INTERVAL);
+        let mut stopped = false;
+        let exit = loop {
+            let probing = probe.is_some() && self.state.lock().unwrap().status == Status::Starting;
+            tokio::select! {
+                r = stdout.read(&mut buf_out), if !out_done => {
+                    let n = r.unwrap_or(0);
+                    if n == 0 { out_done = true; continue; }
+                    self.log(&out_redactor.push(&buf_out[..n]));
+                    self.match_ready(&mut out_line, &buf_out[..n]);
+                }
+                r = stderr.read(&mut buf_err), if !err_done => {
+                    let n = r.unwrap_or(0);
+                    if n == 0 { err_done = true; continue; }
+                    self.log(&err_redactor.push(&buf_err[..n]));
+                    self.match_ready(&mut err_line, &buf_err[..n]);
+                }
+                exit = child.wait(), if out_done && err_done => break exit.ok(),
+                _ = tick.tick(), if probing => {
+                    if probe_port(probe.unwrap_or_default()).await {
+                        self.mark_ready();
+                    }
+                }
+                _ = stop.notified() => { stopped = true; break None; }
+            }
+        };
+        let exit = match exit {
+            Some(exit) => Some(exit),
+            None => {
+                child.terminate(kill_grace).await;
+                child.wait().await.ok()
+            }
+        };
+        // nothing the service started outlives it
+        child.kill();
+        feeder.abort();
+        self.log(&out_redactor.finish());
+        self.log(&err_redactor.finish());
+        let cpu_ms = exit.map_or(0, |e| e.cpu.as_millis() as u64);
+        ctx.record_usage(Usage {
+            exec_cpu_ms: cpu_ms,
+            exec_wall_ms: start.elapsed().as_millis() as u64,
+            ..Usage::default()
+        });
+        let (reason, signal) = match &exit {
+            Some(exit) => termination(exit, false, false, &rlimits),
+            None => ("signal", None),
+        };
+        let last = LastExit {
+            exit_code: exit.and_then(|e| e.status.code()),
+            termination: if stopped { "stopped" } else { reason },
+            signal,
+        };
+        (last, stopped)
+    }
+
+    /// Checks complete lines of raw output against `ready_log`. Only the
+    /// match is used, so secrets in the raw bytes go nowhere.
+    fn match_ready(&self, line: &mut Vec<u8>, data: &[u8]) {
+        let Some(re) = &self.ready_log else { return };
+        line.extend_from_slice(data);
+        while let Some(end) = line.iter().position(|b| *b == b'\n') {
+            if re.is_match(&line[..end]) {
+                self.mark_ready();
+            }
+            line.drain(..=end);
+        }
+        let excess = line.len().saturating_sub(MAX_LINE);
+        line.drain(..excess);
+    }
+}
+
+/// Whether something accepts connections on `port` over loopback, IPv4 or IPv6.
+async fn probe_port(port: u16) -> bool {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    let v4 = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port));
+    let v6 = tokio::net::TcpStream::connect((Ipv6Addr::LOCALHOST, port));
+    let (v4, v6) = tokio::join!(v4, v6);
+    v4.is_ok() || v6.is_ok()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ServiceOp {
+    Start,
+    Stop,
+    Status,
+    Logs,
+}
+
+/// One of the `service_*` tools.
+pub struct ServiceTool {
+    services: Arc<Services>,
+    op: ServiceOp,
+}
+
+impl ServiceTool {
+    pub fn new(services: Arc<Services>, op: ServiceOp) -> Self {
+        Self { services, op }
+    }
+}
+
+#[async_trait]
+impl Tool for ServiceTool {
+    fn capabilities(&self) -> serde_json::Value {
+        let status = json!({"type":"object","properties": {"name":{"type":"string"},"status":{"type":"string","enum":["stopped","starting","ready","stopping","failed"]},"ready":{"type":"boolean"},"started_by":{"type":["string","null"]},"started_at":{"type":["integer","null"]},"restarts":{"type":"integer"},"exit_code":{"type":["integer","null"]},"termination":{"type":["string","null"]},"signal":{"type":["integer","null"]},"log_bytes":{"type":"integer"}}});
+        match self.op {
+            ServiceOp::Start => {
+                json!({"input": {"type":"object","required":["name"],"properties": {"name": {"type":"string"},"wait":{"type":"boolean"}}}, "output": status})
+            }
+            ServiceOp::Stop => {
+                json!({"input": {"type":"object","required":["name"],"properties": {"name": {"type":"string"}}}, "output": status})
+            }
+            ServiceOp::Status => {
+                json!({"input": {"type":"object","properties": {"name": {"type":"string"}}}, "output": {"oneOf": [status, {"type":"object","properties": {"services":{"type":"array","items": status}}}]}})
+            }
+            ServiceOp::Logs => {
+                json!({"input": {"type":"object","required":["name"],"properties": {"name": {"type":"string"},"offset":{"type":"integer"},"limit":{"type":"integer"}}}, "output": {"type":"object","properties": {"data_b64":{"type":"string"},"offset":{"type":"integer"},"next_offset":{"type":"integer"},"total":{"type":"integer"},"dropped":{"type":"integer"}}}})
+            }
+        }
+    }
+
+    async fn call(
+        &self,
+        ctx: &CallContext,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, AppError> {
+        match self.op {
+            ServiceOp::Start => self.services.start(ctx, &params).await,
+            ServiceOp::Stop => self.services.stop(ctx, &params).await,
+            ServiceOp::Status => self.services.status(ctx, &params).await,
+            ServiceOp::Logs => self.services.logs(ctx, &params).await,
+        }
+    }
+}
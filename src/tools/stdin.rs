@@ -0,0 +1,149 @@
+use crate::errors::AppError;
+use std::collections::HashMap;
+use std::fs::File;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::ChildStdin;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Where an exec child's stdin comes from.
+#[derive(Debug)]
+pub enum StdinSource {
+    Null,
+    Bytes(Vec<u8>),
+    /// An open file under root_dir, copied up to the size cap.
+    File(File),
+    /// Chunks sent with `exec_stdin` while a streaming call runs.
+    Stream,
+}
+
+impl StdinSource {
+    pub fn stdio(&self) -> Stdio {
+        match self {
+            StdinSource::Null => Stdio::null(),
+            _ => Stdio::piped(),
+        }
+    }
+
+    /// Writes the input on a task of its own so a child that fills its
+    /// output pipe before reading all of stdin can't deadlock the call.
+    /// Closing stdin when the input runs out is the child's EOF.
+    pub fn feed(
+        self,
+        stdin: Option<ChildStdin>,
+        chunks: Option<mpsc::UnboundedReceiver<Vec<u8>>>,
+        max_bytes: u64,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let Some(mut stdin) = stdin else { return };
+            // a child that exits without reading everything closes the pipe
+            // early; that's its business
+            let _ = match (self, chunks) {
+                (StdinSource::Null, _) | (StdinSource::Stream, None) => Ok(()),
+                (StdinSource::Bytes(bytes), _) => stdin.write_all(&bytes).await,
+                (StdinSource::File(file), _) => {
+                    let mut file = tokio::fs::File::from_std(file).take(max_bytes);
+                    tokio::io::copy(&mut file, &mut stdin).await.map(drop)
+                }
+                (StdinSource::Stream, Some(mut chunks)) => {
+                    while let Some(chunk) = chunks.recv().await {
+                        if stdin.write_all(&chunk).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(())
+                }
+            };
+        })
+    }
+}
+
+/// Stdin of running streaming exec calls, by the id announced in their
+/// `start` event. Only the principal that started a call may feed it.
+pub struct StdinStreams {
+    max_bytes: usize,
+    open: Mutex<HashMap<String, OpenStream>>,
+}
+
+struct OpenStream {
+    principal: String,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    sent: usize,
+}
+
+impl StdinStreams {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            open: Mutex::default(),
+        }
+    }
+
+    /// Registers a stream. Dropping the returned handle unregisters it.
+    pub fn open(
+        self: &Arc<Self>,
+        principal: &str,
+    ) -> (StdinHandle, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let stream = OpenStream {
+            principal: principal.to_string(),
+            tx,
+            sent: 0,
+        };
+        self.open.lock().unwrap().insert(id.clone(), stream);
+        let handle = StdinHandle {
+            streams: self.clone(),
+            id,
+        };
+        (handle, rx)
+    }
+
+    /// Queues `data` for the child; `eof` closes its stdin afterwards.
+    /// Returns the bytes sent so far.
+    pub fn send(
+        &self,
+        principal: &str,
+        id: &str,
+        data: Vec<u8>,
+        eof: bool,
+    ) -> Result<usize, AppError> {
+        let mut open = self.open.lock().unwrap();
+        let Some(stream) = open.get_mut(id).filter(|s| s.principal == principal) else {
+            return Err(AppError::NotFound);
+        };
+        if stream.sent + data.len() > self.max_bytes {
+            return Err(AppError::ToolError("stdin exceeds max_stdin_kb".into()));
+        }
+        stream.sent += data.len();
+        let sent = stream.sent;
+        if !data.is_empty() && stream.tx.send(data).is_err() {
+            // the child is gone
+            open.remove(id);
+            return Err(AppError::NotFound);
+        }
+        if eof {
+            open.remove(id);
+        }
+        Ok(sent)
+    }
+}
+
+pub struct StdinHandle {
+    streams: Arc<StdinStreams>,
+    id: String,
+}
+
+impl StdinHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Drop for StdinHandle {
+    fn drop(&mut self) {
+        self.streams.open.lock().unwrap().remove(&self.id);
+    }
+}